@@ -1,10 +1,19 @@
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    #[cfg(all(feature = "proto"))]
+    #[cfg(feature = "proto")]
     tonic_build::configure()
         .build_server(true)
         .build_client(false)
-        .out_dir("src/api/")
-        .compile(&["src/api/zrapi.proto"], &["src/api"])
+        .out_dir("src/grcp/")
+        // Served by the reflection service
+        .file_descriptor_set_path("src/grcp/descriptor.bin")
+        .compile(
+            &[
+                "src/grcp/zrapi.proto",
+                "src/grcp/health.proto",
+                "src/grcp/reflection.proto",
+            ],
+            &["src/grcp"],
+        )
         .unwrap();
     Ok(())
 }
@@ -5,8 +5,8 @@ use axum::routing::get;
 use axum::routing::post;
 use axum::Json;
 use axum::Router;
+use axum::extract::{FromRequest, FromRequestParts};
 use futures::stream::Stream;
-use futures::StreamExt;
 use lazy_static::lazy_static;
 use serde::{Deserialize, Serialize};
 use std::ffi::CString;
@@ -14,8 +14,60 @@ use std::sync::Arc;
 use std::sync::Mutex;
 use std::thread;
 use switch_sys::*;
-use tokio::sync::broadcast;
+use crate::limit::{Limits, Permit};
+use crate::replay::{Item, Replay, Subscriber};
 use tokio::sync::mpsc;
+use utoipa::{OpenApi, ToSchema};
+
+/// What an authenticated client may do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Scope {
+    /// Subscribe to events and read state.
+    Events,
+    /// Everything, including running commands.
+    Command,
+}
+
+impl Scope {
+    fn from_str(value: &str) -> Option<Scope> {
+        if value.eq_ignore_ascii_case("events") || value.eq_ignore_ascii_case("read") {
+            Some(Scope::Events)
+        } else if value.eq_ignore_ascii_case("command") || value.eq_ignore_ascii_case("all") {
+            Some(Scope::Command)
+        } else {
+            None
+        }
+    }
+
+    fn allows(self, required: Scope) -> bool {
+        self == Scope::Command || required == Scope::Events
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Scope::Events => "events",
+            Scope::Command => "command",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct ApiKey {
+    pub name: String,
+    pub key: String,
+    pub scope: Scope,
+    /// Command policy, empty uses the profile one
+    pub policy: String,
+}
+
+/// The client a request was authenticated as.
+#[derive(Debug, Clone)]
+struct Identity {
+    pub name: String,
+    pub scope: Scope,
+    /// Command policy, empty allows every command
+    pub policy: String,
+}
 
 #[derive(Debug, Clone)]
 struct Profile {
@@ -24,6 +76,25 @@ struct Profile {
     pub listen_port: u16,
     pub password: String,
     pub apply_inbound_acl: String,
+    pub api_keys: Vec<ApiKey>,
+    pub tls_cert: String,
+    pub tls_key: String,
+    pub tls_client_ca: String,
+    pub event_replay_size: usize,
+    /// Requests per second per client, 0 is unlimited
+    pub rate_limit: f64,
+    pub rate_burst: u32,
+    /// Concurrent blocking executions, 0 is unlimited
+    pub max_concurrent: usize,
+    /// Command policy of the acl, password and api keys without one
+    pub command_policy: String,
+    /// Origins browsers may call from, `*` is any, empty disables CORS
+    pub cors_origins: Vec<String>,
+    pub cors_methods: Vec<String>,
+    /// Signs the query tokens of `/api/token`
+    pub token_secret: String,
+    /// Longest lifetime of a query token in seconds
+    pub token_ttl: u64,
     pub enable: bool,
 }
 
@@ -35,15 +106,189 @@ impl Profile {
             listen_port: 8202,
             password: "".to_string(),
             apply_inbound_acl: "".to_string(),
+            api_keys: Vec::new(),
+            tls_cert: "".to_string(),
+            tls_key: "".to_string(),
+            tls_client_ca: "".to_string(),
+            event_replay_size: 1024,
+            rate_limit: 0.0,
+            rate_burst: 0,
+            max_concurrent: 0,
+            command_policy: "".to_string(),
+            cors_origins: Vec::new(),
+            cors_methods: vec![String::from("GET"), String::from("POST"), String::from("DELETE")],
+            token_secret: "".to_string(),
+            token_ttl: 60,
             enable: false,
         }
     }
+
+    /// Find who the `authorization` header belongs to.
+    ///
+    /// `Bearer <key>` and `Basic <name:key>` match the api keys,
+    /// `Bearer <md5(password)>` and `Basic <any:password>` match the password.
+    fn authenticate(&self, authorization: &str) -> Option<Identity> {
+        use base64::Engine;
+        let (kind, credentials) = authorization.trim().split_once(' ')?;
+        let credentials = credentials.trim();
+        if kind.eq_ignore_ascii_case("bearer") {
+            for api_key in &self.api_keys {
+                if crate::token::secret_eq(credentials, &api_key.key) {
+                    return Some(Identity {
+                        name: api_key.name.clone(),
+                        scope: api_key.scope,
+                        policy: self.key_policy(api_key),
+                    });
+                }
+            }
+            if !self.password.is_empty()
+                && crate::token::secret_eq(
+                    &credentials.to_ascii_lowercase(),
+                    &format!("{:x}", md5::compute(&self.password)),
+                )
+            {
+                return Some(Identity {
+                    name: String::from("password"),
+                    scope: Scope::Command,
+                    policy: self.command_policy.clone(),
+                });
+            }
+        } else if kind.eq_ignore_ascii_case("basic") {
+            let decoded = base64::engine::general_purpose::STANDARD
+                .decode(credentials)
+                .ok()?;
+            let decoded = String::from_utf8(decoded).ok()?;
+            let (name, key) = decoded.split_once(':')?;
+            for api_key in &self.api_keys {
+                if api_key.name == name && crate::token::secret_eq(key, &api_key.key) {
+                    return Some(Identity {
+                        name: api_key.name.clone(),
+                        scope: api_key.scope,
+                        policy: self.key_policy(api_key),
+                    });
+                }
+            }
+            // The user name is not checked, so it must not become the identity.
+            if !self.password.is_empty() && crate::token::secret_eq(key, &self.password) {
+                return Some(Identity {
+                    name: String::from("password"),
+                    scope: Scope::Command,
+                    policy: self.command_policy.clone(),
+                });
+            }
+        }
+        None
+    }
+
+    /// Find who a query token was minted for, it only opens the streams.
+    fn authenticate_token(&self, token: &str) -> Option<Identity> {
+        match crate::token::verify(&self.token_secret, token, TOKEN_AUDIENCE) {
+            Ok(claims) => Some(Identity {
+                name: claims.sub,
+                scope: Scope::from_str(&claims.scope).unwrap_or(Scope::Events),
+                policy: claims.policy,
+            }),
+            Err(e) => {
+                debug!("API token {}", e);
+                None
+            }
+        }
+    }
+
+    /// Cross origin requests from the configured origins, `None` when there are none.
+    fn cors(&self) -> Option<tower_http::cors::CorsLayer> {
+        use axum::http::{header, HeaderName, HeaderValue, Method};
+        use tower_http::cors::{AllowOrigin, CorsLayer};
+        if self.cors_origins.is_empty() {
+            return None;
+        }
+        let origins = if self.cors_origins.iter().any(|origin| origin == "*") {
+            AllowOrigin::any()
+        } else {
+            AllowOrigin::list(
+                self.cors_origins
+                    .iter()
+                    .filter_map(|origin| match HeaderValue::from_str(origin) {
+                        Ok(origin) => Some(origin),
+                        Err(_) => {
+                            warn!("Invalid cors origin [{}], skipped", origin);
+                            None
+                        }
+                    }),
+            )
+        };
+        let methods: Vec<Method> = self
+            .cors_methods
+            .iter()
+            .filter_map(|method| match Method::from_bytes(method.to_ascii_uppercase().as_bytes()) {
+                Ok(method) => Some(method),
+                Err(_) => {
+                    warn!("Invalid cors method [{}], skipped", method);
+                    None
+                }
+            })
+            .collect();
+        Some(
+            CorsLayer::new()
+                .allow_origin(origins)
+                .allow_methods(methods)
+                .allow_headers([header::AUTHORIZATION, header::CONTENT_TYPE, HeaderName::from_static("last-event-id")])
+                .expose_headers([header::RETRY_AFTER])
+                .max_age(std::time::Duration::from_secs(600)),
+        )
+    }
+
+    fn key_policy(&self, api_key: &ApiKey) -> String {
+        if api_key.policy.is_empty() {
+            self.command_policy.clone()
+        } else {
+            api_key.policy.clone()
+        }
+    }
+
+    /// Find who the verified client certificate belongs to,
+    /// the common name is looked up in the api key names.
+    fn authenticate_cert(&self, common_name: &str) -> Option<Identity> {
+        self.api_keys
+            .iter()
+            .find(|api_key| api_key.name == common_name)
+            .map(|api_key| Identity {
+                name: api_key.name.clone(),
+                scope: api_key.scope,
+                policy: self.key_policy(api_key),
+            })
+    }
+}
+
+/// Routes that only read state, everything else needs the command scope.
+/// A token carries the scope of whoever minted it.
+const READ_ONLY_ROUTES: [&str; 4] = ["/api/sse/event", "/api/storage/list", "/api/call/getvar", "/api/token"];
+
+/// Audience of the query tokens of this API.
+const TOKEN_AUDIENCE: &str = "api";
+
+fn required_scope(request: &axum::extract::Request) -> Scope {
+    if request.method() == axum::http::Method::GET
+        || READ_ONLY_ROUTES.contains(&request.uri().path())
+    {
+        Scope::Events
+    } else {
+        Scope::Command
+    }
 }
 
+/// Largest JSON body kept for the audit log, same as the `Json` extractor.
+const AUDIT_BODY_LIMIT: usize = 2 * 1024 * 1024;
+
+/// Routes that stream instead of running a blocking command,
+/// they also take a query token as browsers cannot set headers there.
+const STREAM_ROUTES: [&str; 2] = ["/api/sse/event", "/api/ws"];
+
 struct Global {
     running: Mutex<bool>,
     profile: Mutex<Profile>,
-    ev_tx: Mutex<Option<broadcast::Sender<switch_sys::Event>>>,
+    limits: Mutex<Arc<Limits>>,
+    events: Mutex<Option<Arc<Replay<switch_sys::Event>>>>,
     done_tx: Mutex<Option<mpsc::Sender<u8>>>,
 }
 
@@ -52,7 +297,8 @@ impl Global {
         Global {
             profile: Mutex::new(Profile::new()),
             running: Mutex::new(false),
-            ev_tx: Mutex::new(None),
+            limits: Mutex::new(Limits::new(0.0, 0, 0)),
+            events: Mutex::new(None),
             done_tx: Mutex::new(None),
         }
     }
@@ -81,7 +327,7 @@ pub fn shutdown() {
         if *GOLOBAS.running.lock().unwrap() == false {
             debug!("HTTP API server shutdown");
             *GOLOBAS.done_tx.lock().unwrap() = None;
-            *GOLOBAS.ev_tx.lock().unwrap() = None;
+            *GOLOBAS.events.lock().unwrap() = None;
             break;
         }
     }
@@ -112,57 +358,214 @@ pub fn load_config(cfg: switch_xml_t) {
                     GOLOBAS.profile.lock().unwrap().password = val;
                 } else if var.eq_ignore_ascii_case("apply-inbound-acl") {
                     GOLOBAS.profile.lock().unwrap().apply_inbound_acl = val;
+                } else if var.eq_ignore_ascii_case("tls-cert") {
+                    GOLOBAS.profile.lock().unwrap().tls_cert = val;
+                } else if var.eq_ignore_ascii_case("tls-key") {
+                    GOLOBAS.profile.lock().unwrap().tls_key = val;
+                } else if var.eq_ignore_ascii_case("tls-client-ca") {
+                    GOLOBAS.profile.lock().unwrap().tls_client_ca = val;
+                } else if var.eq_ignore_ascii_case("event-replay-size") {
+                    GOLOBAS.profile.lock().unwrap().event_replay_size =
+                        val.parse::<usize>().unwrap_or(1024);
+                } else if var.eq_ignore_ascii_case("rate-limit") {
+                    GOLOBAS.profile.lock().unwrap().rate_limit = val.parse::<f64>().unwrap_or(0.0);
+                } else if var.eq_ignore_ascii_case("rate-burst") {
+                    GOLOBAS.profile.lock().unwrap().rate_burst = val.parse::<u32>().unwrap_or(0);
+                } else if var.eq_ignore_ascii_case("max-concurrent") {
+                    GOLOBAS.profile.lock().unwrap().max_concurrent =
+                        val.parse::<usize>().unwrap_or(0);
+                } else if var.eq_ignore_ascii_case("command-policy") {
+                    GOLOBAS.profile.lock().unwrap().command_policy = val;
+                } else if var.eq_ignore_ascii_case("cors-origins") {
+                    GOLOBAS.profile.lock().unwrap().cors_origins = split_list(&val);
+                } else if var.eq_ignore_ascii_case("cors-methods") {
+                    GOLOBAS.profile.lock().unwrap().cors_methods = split_list(&val);
+                } else if var.eq_ignore_ascii_case("token-secret") {
+                    GOLOBAS.profile.lock().unwrap().token_secret = val;
+                } else if var.eq_ignore_ascii_case("token-ttl") {
+                    GOLOBAS.profile.lock().unwrap().token_ttl = val.parse::<u64>().unwrap_or(60).max(1);
                 } else if var.eq_ignore_ascii_case("enable") {
                     GOLOBAS.profile.lock().unwrap().enable = switch_true(&val);
                 }
 
                 param = (*param).next;
             }
+
+            let tmp_str = CString::new("api-keys").unwrap();
+            let keys_tag = switch_sys::switch_xml_child(settings_tag, tmp_str.as_ptr());
+            if !keys_tag.is_null() {
+                let tmp_str = CString::new("api-key").unwrap();
+                let mut key_tag = switch_sys::switch_xml_child(keys_tag, tmp_str.as_ptr());
+                while !key_tag.is_null() {
+                    let tmp_str = CString::new("name").unwrap();
+                    let name = switch_to_string(switch_xml_attr_soft(key_tag, tmp_str.as_ptr()));
+                    let tmp_str = CString::new("key").unwrap();
+                    let key = switch_to_string(switch_xml_attr_soft(key_tag, tmp_str.as_ptr()));
+                    let tmp_str = CString::new("scope").unwrap();
+                    let scope = switch_to_string(switch_xml_attr_soft(key_tag, tmp_str.as_ptr()));
+                    let tmp_str = CString::new("policy").unwrap();
+                    let policy = switch_to_string(switch_xml_attr_soft(key_tag, tmp_str.as_ptr()));
+
+                    match Scope::from_str(&scope) {
+                        Some(scope) if !name.is_empty() && !key.is_empty() => {
+                            GOLOBAS.profile.lock().unwrap().api_keys.push(ApiKey {
+                                name,
+                                key,
+                                scope,
+                                policy,
+                            });
+                        }
+                        _ => {
+                            warn!("Invalid api key [{}] scope [{}], skipped", name, scope);
+                        }
+                    }
+                    key_tag = (*key_tag).next;
+                }
+            }
         }
     }
+    let mut profile = GOLOBAS.profile.lock().unwrap();
+    if profile.token_secret.is_empty() {
+        profile.token_secret = crate::token::random_secret();
+    }
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|item| item.trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect()
 }
 
 fn on_event(ev: switch_sys::Event) {
-    let tx = GOLOBAS.ev_tx.lock().unwrap().clone();
-    if tx.is_some() {
-        let ret = tx.unwrap().send(ev);
-        if let Err(e) = ret {
-            error!("{}", e);
-        }
+    let events = GOLOBAS.events.lock().unwrap().clone();
+    if let Some(events) = events {
+        events.publish(ev);
     }
 }
 
-#[derive(Serialize)]
+/// The body of every reply, `code` is also the HTTP status.
+#[derive(Serialize, ToSchema)]
+#[aliases(Reply = ApiResponse<serde_json::Value>)]
 struct ApiResponse<T> {
     code: i32,
     message: String,
+    #[schema(value_type = Option<Object>)]
     data: Option<T>,
 }
 
+/// The replies any endpoint running a command may give besides its own, for the OpenAPI document.
+#[derive(utoipa::IntoResponses)]
+#[allow(dead_code)]
+enum Rejections {
+    /// Unauthorized
+    #[response(status = 401)]
+    Unauthorized(Reply),
+    /// Forbidden
+    #[response(status = 403)]
+    Forbidden(Reply),
+    /// Too many requests
+    #[response(status = 429)]
+    TooManyRequests(Reply),
+    /// Failed
+    #[response(status = 500)]
+    Failed(Reply),
+}
+
 impl<T> IntoResponse for ApiResponse<T>
 where
     T: Serialize,
 {
     fn into_response(self) -> axum::response::Response {
-        (axum::http::StatusCode::OK, Json(self)).into_response()
+        let status = u16::try_from(self.code)
+            .ok()
+            .and_then(|code| StatusCode::from_u16(code).ok())
+            .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        (status, Json(self)).into_response()
+    }
+}
+
+impl ApiResponse<()> {
+    fn rejection(status: StatusCode, message: String) -> ApiResponse<()> {
+        ApiResponse {
+            code: status.as_u16() as i32,
+            message,
+            data: None,
+        }
+    }
+}
+
+impl From<axum::extract::rejection::JsonRejection> for ApiResponse<()> {
+    fn from(rejection: axum::extract::rejection::JsonRejection) -> Self {
+        ApiResponse::rejection(rejection.status(), rejection.body_text())
+    }
+}
+
+impl From<axum::extract::rejection::QueryRejection> for ApiResponse<()> {
+    fn from(rejection: axum::extract::rejection::QueryRejection) -> Self {
+        ApiResponse::rejection(rejection.status(), rejection.body_text())
+    }
+}
+
+impl From<axum::extract::rejection::PathRejection> for ApiResponse<()> {
+    fn from(rejection: axum::extract::rejection::PathRejection) -> Self {
+        ApiResponse::rejection(rejection.status(), rejection.body_text())
+    }
+}
+
+/// `Json` that rejects with an `ApiResponse`.
+#[derive(FromRequest)]
+#[from_request(via(axum::Json), rejection(ApiResponse<()>))]
+struct ApiJson<T>(T);
+
+/// `Query` that rejects with an `ApiResponse`.
+#[derive(FromRequestParts)]
+#[from_request(via(axum::extract::Query), rejection(ApiResponse<()>))]
+struct ApiQuery<T>(T);
+
+/// `Path` that rejects with an `ApiResponse`.
+#[derive(FromRequestParts)]
+#[from_request(via(axum::extract::Path), rejection(ApiResponse<()>))]
+struct ApiPath<T>(T);
+
+impl From<axum::extract::multipart::MultipartRejection> for ApiResponse<()> {
+    fn from(rejection: axum::extract::multipart::MultipartRejection) -> Self {
+        ApiResponse::rejection(rejection.status(), rejection.body_text())
+    }
+}
+
+/// `Multipart` that rejects with an `ApiResponse`.
+struct ApiMultipart(axum::extract::Multipart);
+
+#[axum::async_trait]
+impl<S: Send + Sync> FromRequest<S> for ApiMultipart {
+    type Rejection = ApiResponse<()>;
+
+    async fn from_request(request: axum::extract::Request, state: &S) -> Result<Self, Self::Rejection> {
+        axum::extract::Multipart::from_request(request, state)
+            .await
+            .map(ApiMultipart)
+            .map_err(ApiResponse::from)
     }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 struct Request {}
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 struct ModuleRequest {
     name: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 struct JSAPIRequest {
     command: String,
+    #[schema(value_type = Object)]
     args: serde_json::Value,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 struct SendEventRequest {
     event_id: u32,
     subclass_name: String,
@@ -170,62 +573,333 @@ struct SendEventRequest {
     body: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 struct SendMsgRequest {
     uuid: String,
     headers: std::collections::HashMap<String, String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 struct CommandRequest {
     command: String,
     args: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+struct JobRequest {
+    command: String,
+    #[serde(default)]
+    args: String,
+    /// Where to POST the finished job
+    #[serde(default)]
+    webhook: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 struct Topic {
     name: String,
     subclass_name: String,
     id: Option<u32>,
+    /// All filters must match
+    #[serde(default)]
+    filters: Vec<crate::filter::HeaderFilter>,
+    #[serde(skip)]
+    predicates: Vec<crate::filter::Predicate>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 struct SubscribeRequest {
     topics: Vec<Topic>,
+    /// Only send these headers, empty sends all
+    #[serde(default)]
+    headers: Vec<String>,
+    /// Only send events of these channels and their bridged legs,
+    /// the stream ends when all of them hung up
+    #[serde(default)]
+    uuids: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+struct StorageListRequest {
+    profile: String,
+    #[serde(default)]
+    prefix: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+struct StoragePurgeRequest {
+    profile: String,
+    url: String,
+    #[serde(default)]
+    prefix: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+struct StoragePrefetchRequest {
+    profile: String,
+    urls: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+struct StorageResyncRequest {
+    profile: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct QueryToken {
+    token: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, ToSchema)]
+struct TokenRequest {
+    /// Seconds the token is valid, at most and by default `token-ttl`
+    #[serde(default)]
+    ttl: u64,
+}
+
+#[derive(Debug, Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+struct SseQuery {
+    /// The `SubscribeRequest` as JSON, for clients that cannot send a body
+    subscribe: Option<String>,
+    /// Query token from `/api/token`
+    #[allow(dead_code)]
+    token: Option<String>,
+}
+
+#[derive(Debug, Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+struct SoundUploadQuery {
+    /// Sub directory the files go to
+    #[serde(default)]
+    path: String,
+    /// Convert to this sample rate, 0 uses the configured one
+    #[serde(default)]
+    rate: u32,
+    /// Replace existing files
+    #[serde(default)]
+    overwrite: bool,
+}
+
+#[derive(Debug, Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+struct SoundListQuery {
+    /// Only list this sub directory
+    #[serde(default)]
+    path: String,
+}
+
+/// A 403 reply if the policy of `identity` does not allow `command args`.
+fn denied<T>(identity: &Identity, command: &str, args: &str) -> Option<ApiResponse<T>> {
+    crate::policy::check(&identity.policy, &identity.name, command, args)
+        .err()
+        .map(|message| ApiResponse {
+            code: 403,
+            message,
+            data: None,
+        })
+}
+
+/// Rate limits apply per identity and address.
+fn client_key(identity: &Identity, remote_addr: &str) -> String {
+    format!("{}@{}", identity.name, remote_addr)
+}
+
+/// Take a token for `client` and, for blocking work, a concurrency slot.
+fn admit(client: &str, blocking: bool) -> Result<Option<Permit>, ApiResponse<()>> {
+    let limits = GOLOBAS.limits.lock().unwrap().clone();
+    if !limits.check(client) {
+        return Err(ApiResponse {
+            code: 429,
+            message: String::from("-ERR Too many requests"),
+            data: None,
+        });
+    }
+    if !blocking {
+        return Ok(None);
+    }
+    match limits.acquire() {
+        Some(permit) => Ok(Some(permit)),
+        None => Err(ApiResponse {
+            code: 429,
+            message: String::from("-ERR Too many concurrent requests"),
+            data: None,
+        }),
+    }
 }
 
 async fn auth_middleware(
     axum::extract::ConnectInfo(addr): axum::extract::ConnectInfo<std::net::SocketAddr>,
-    request: axum::extract::Request,
+    mut request: axum::extract::Request,
     next: axum::middleware::Next,
 ) -> impl IntoResponse {
     let remote_addr_str = addr.ip().to_string();
-    let acl = GOLOBAS.profile.lock().unwrap().apply_inbound_acl.clone();
-    if switch_sys::check_acl(&remote_addr_str, &acl) {
+    let profile = GOLOBAS.profile.lock().unwrap().clone();
+    let common_name = request
+        .extensions()
+        .get::<crate::tls::PeerCommonName>()
+        .and_then(|peer| peer.0.clone());
+    let token = STREAM_ROUTES
+        .contains(&request.uri().path())
+        .then(|| axum::extract::Query::<QueryToken>::try_from_uri(request.uri()).ok())
+        .flatten()
+        .and_then(|query| query.0.token);
+    let identity = if switch_sys::check_acl(&remote_addr_str, &profile.apply_inbound_acl) {
+        Some(Identity {
+            name: String::from("acl"),
+            scope: Scope::Command,
+            policy: profile.command_policy.clone(),
+        })
+    } else if let Some(identity) = common_name.and_then(|cn| profile.authenticate_cert(&cn)) {
+        Some(identity)
+    } else if let Some(identity) = request
+        .headers()
+        .get(axum::http::header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| profile.authenticate(value))
+    {
+        Some(identity)
+    } else {
+        token.and_then(|token| profile.authenticate_token(&token))
+    };
+
+    match identity {
+        Some(identity) => {
+            request.extensions_mut().insert(identity);
+            next.run(request).await
+        }
+        None => {
+            debug!(
+                "API [{}] {} from {} authentication failure",
+                request.method(),
+                request.uri(),
+                remote_addr_str
+            );
+            // Recorded here, the audit log never buffers bodies of strangers
+            let operation = format!("{} {}", request.method(), request.uri().path());
+            let pending = crate::audit::Pending::new("http", &operation, serde_json::Value::Null);
+            pending.finish("", &remote_addr_str, 401);
+            ApiResponse {
+                code: 401,
+                message: StatusCode::UNAUTHORIZED.to_string(),
+                data: Some(()),
+            }
+            .into_response()
+        }
+    }
+}
+
+/// Check the scope and limits of an authenticated request and run it.
+async fn authorize_middleware(
+    axum::extract::ConnectInfo(addr): axum::extract::ConnectInfo<std::net::SocketAddr>,
+    axum::Extension(identity): axum::Extension<Identity>,
+    request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> axum::response::Response {
+    let remote_addr_str = addr.ip().to_string();
+    if !identity.scope.allows(required_scope(&request)) {
         debug!(
-            "[{}] {} from {}",
+            "API [{}] {} from {} [{}] forbidden",
             request.method(),
             request.uri(),
-            addr.to_string()
+            remote_addr_str,
+            identity.name
         );
-        return next.run(request).await;
+        return ApiResponse {
+            code: 403,
+            message: StatusCode::FORBIDDEN.to_string(),
+            data: Some(()),
+        }
+        .into_response();
     }
     debug!(
-        "API [{}] {} from {} authentication failure",
+        "[{}] {} from {} [{}]",
         request.method(),
         request.uri(),
-        remote_addr_str
+        remote_addr_str,
+        identity.name
     );
-    ApiResponse {
-        code: 401,
-        message: StatusCode::UNAUTHORIZED.to_string(),
-        data: Some(()),
+    let blocking = !STREAM_ROUTES.contains(&request.uri().path());
+    let _permit = match admit(&client_key(&identity, &remote_addr_str), blocking) {
+        Ok(permit) => permit,
+        Err(response) => {
+            debug!(
+                "API [{}] {} from {} [{}] {}",
+                request.method(),
+                request.uri(),
+                remote_addr_str,
+                identity.name,
+                response.message
+            );
+            return ([(axum::http::header::RETRY_AFTER, "1")], response).into_response();
+        }
+    };
+    next.run(request).await
+}
+
+/// Record every authenticated request in the audit log, JSON bodies are buffered for it.
+async fn audit_middleware(
+    axum::extract::ConnectInfo(addr): axum::extract::ConnectInfo<std::net::SocketAddr>,
+    request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> axum::response::Response {
+    if !crate::audit::enabled() {
+        return next.run(request).await;
     }
-    .into_response()
+    let remote_addr_str = addr.ip().to_string();
+    let identity = request
+        .extensions()
+        .get::<Identity>()
+        .map(|identity| identity.name.clone())
+        .unwrap_or_default();
+    let operation = format!("{} {}", request.method(), request.uri().path());
+    let mut args = axum::extract::Query::<std::collections::HashMap<String, String>>::try_from_uri(
+        request.uri(),
+    )
+    .map(|query| serde_json::json!(query.0))
+    .unwrap_or(serde_json::Value::Null);
+    let json = request
+        .headers()
+        .get(axum::http::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"));
+    let request = if json {
+        let (parts, body) = request.into_parts();
+        let bytes = match axum::body::to_bytes(body, AUDIT_BODY_LIMIT).await {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                let response = ApiResponse::rejection(StatusCode::BAD_REQUEST, e.to_string());
+                crate::audit::Pending::new("http", &operation, args).finish(&identity, &remote_addr_str, 400);
+                return response.into_response();
+            }
+        };
+        args = serde_json::from_slice(&bytes)
+            .unwrap_or_else(|_| serde_json::Value::String(String::from_utf8_lossy(&bytes).into_owned()));
+        axum::extract::Request::from_parts(parts, axum::body::Body::from(bytes))
+    } else {
+        request
+    };
+
+    let pending = crate::audit::Pending::new("http", &operation, args);
+    let response = next.run(request).await;
+    pending.finish(&identity, &remote_addr_str, response.status().as_u16() as i32);
+    response
 }
 
 // Reload XML
-async fn hander_reload_xml() -> impl IntoResponse {
+#[utoipa::path(
+    post,
+    path = "/api/reloadxml",
+    tag = "system",
+    responses(
+        (status = 200, description = "OK", body = Reply),
+        Rejections,
+    )
+)]
+async fn hander_reload_xml(axum::Extension(identity): axum::Extension<Identity>) -> impl IntoResponse {
+    if let Some(response) = denied(&identity, "reloadxml", "") {
+        return response;
+    }
     let handle = tokio::task::spawn_blocking(|| switch_sys::api_exec("reloadxml", ""));
     let res = handle.await.unwrap();
     match res {
@@ -243,7 +917,19 @@ async fn hander_reload_xml() -> impl IntoResponse {
 }
 
 // Reload ACL
-async fn hander_reload_acl() -> impl IntoResponse {
+#[utoipa::path(
+    post,
+    path = "/api/reloadacl",
+    tag = "system",
+    responses(
+        (status = 200, description = "OK", body = Reply),
+        Rejections,
+    )
+)]
+async fn hander_reload_acl(axum::Extension(identity): axum::Extension<Identity>) -> impl IntoResponse {
+    if let Some(response) = denied(&identity, "reloadacl", "") {
+        return response;
+    }
     let handle = tokio::task::spawn_blocking(|| switch_sys::api_exec("reloadacl", ""));
     let res = handle.await.unwrap();
     match res {
@@ -261,7 +947,24 @@ async fn hander_reload_acl() -> impl IntoResponse {
 }
 
 /// Reload mod
-async fn hander_reload_mod(Json(request): Json<ModuleRequest>) -> impl IntoResponse {
+#[utoipa::path(
+    post,
+    path = "/api/module/reload",
+    tag = "system",
+    request_body = ModuleRequest,
+    responses(
+        (status = 200, description = "OK", body = Reply),
+        (status = 400, description = "Invalid request", body = Reply),
+        Rejections,
+    )
+)]
+async fn hander_reload_mod(
+    axum::Extension(identity): axum::Extension<Identity>,
+    ApiJson(request): ApiJson<ModuleRequest>,
+) -> impl IntoResponse {
+    if let Some(response) = denied(&identity, "reload", &request.name) {
+        return response;
+    }
     let mut cmd = "reload";
     let mut args = request.name;
 
@@ -287,7 +990,24 @@ async fn hander_reload_mod(Json(request): Json<ModuleRequest>) -> impl IntoRespo
 }
 
 /// Load mod
-async fn hander_load_mod(Json(request): Json<ModuleRequest>) -> impl IntoResponse {
+#[utoipa::path(
+    post,
+    path = "/api/module/load",
+    tag = "system",
+    request_body = ModuleRequest,
+    responses(
+        (status = 200, description = "OK", body = Reply),
+        (status = 400, description = "Invalid request", body = Reply),
+        Rejections,
+    )
+)]
+async fn hander_load_mod(
+    axum::Extension(identity): axum::Extension<Identity>,
+    ApiJson(request): ApiJson<ModuleRequest>,
+) -> impl IntoResponse {
+    if let Some(response) = denied(&identity, "load", &request.name) {
+        return response;
+    }
     let handle = tokio::task::spawn_blocking(move || switch_sys::api_exec("load", &request.name));
     let res = handle.await.unwrap();
     match res {
@@ -305,10 +1025,28 @@ async fn hander_load_mod(Json(request): Json<ModuleRequest>) -> impl IntoRespons
 }
 
 /// Unload mod
-async fn hander_unload_mod(Json(request): Json<ModuleRequest>) -> impl IntoResponse {
+#[utoipa::path(
+    post,
+    path = "/api/module/unload",
+    tag = "system",
+    request_body = ModuleRequest,
+    responses(
+        (status = 200, description = "OK", body = Reply),
+        (status = 400, description = "Invalid request", body = Reply),
+        (status = 409, description = "mod_rustit cannot be unloaded", body = Reply),
+        Rejections,
+    )
+)]
+async fn hander_unload_mod(
+    axum::Extension(identity): axum::Extension<Identity>,
+    ApiJson(request): ApiJson<ModuleRequest>,
+) -> impl IntoResponse {
+    if let Some(response) = denied(&identity, "unload", &request.name) {
+        return response;
+    }
     if request.name.contains("mod_rustit") {
         return ApiResponse {
-            code: 501,
+            code: 409,
             message: String::from("-ERR Module mod_rustit is in use, cannot unload"),
             data: Some(()),
         };
@@ -331,7 +1069,30 @@ async fn hander_unload_mod(Json(request): Json<ModuleRequest>) -> impl IntoRespo
 }
 
 /// JSAPI
-async fn hander_jsapi(Json(request): Json<JSAPIRequest>) -> impl IntoResponse {
+#[utoipa::path(
+    post,
+    path = "/api/jsapi",
+    tag = "command",
+    request_body = JSAPIRequest,
+    responses(
+        (status = 200, description = "OK", body = Reply),
+        (status = 400, description = "Invalid request", body = Reply),
+        Rejections,
+    )
+)]
+async fn hander_jsapi(
+    axum::Extension(identity): axum::Extension<Identity>,
+    ApiJson(request): ApiJson<JSAPIRequest>,
+) -> impl IntoResponse {
+    jsapi(&identity, request).await
+}
+
+/// Run a json api command, shared by the HTTP and WebSocket handlers.
+async fn jsapi(identity: &Identity, request: JSAPIRequest) -> ApiResponse<serde_json::Value> {
+    let (policy_cmd, policy_args) = crate::policy::jsapi_command(&request.command, &request.args);
+    if let Some(response) = denied(identity, &policy_cmd, &policy_args) {
+        return response;
+    }
     let cmd = serde_json::json!({"data": request.args, "command": &request.command});
     let cmd = cmd.to_string();
     let mut json_format = false;
@@ -388,7 +1149,25 @@ async fn hander_jsapi(Json(request): Json<JSAPIRequest>) -> impl IntoResponse {
 }
 
 /// SendMsg sends messages to FreeSWITCH and returns a response..
-async fn hander_send_msg(Json(request): Json<SendMsgRequest>) -> impl IntoResponse {
+#[utoipa::path(
+    post,
+    path = "/api/send/msg",
+    tag = "command",
+    request_body = SendMsgRequest,
+    responses(
+        (status = 200, description = "OK", body = Reply),
+        (status = 400, description = "Invalid request", body = Reply),
+        Rejections,
+    )
+)]
+async fn hander_send_msg(
+    axum::Extension(identity): axum::Extension<Identity>,
+    ApiJson(request): ApiJson<SendMsgRequest>,
+) -> impl IntoResponse {
+    let (policy_cmd, policy_args) = crate::policy::sendmsg_command(&request.headers);
+    if let Some(response) = denied(&identity, &policy_cmd, &policy_args) {
+        return response;
+    }
     let handle: tokio::task::JoinHandle<Result<String, String>> =
         tokio::task::spawn_blocking(move || switch_sys::sendmsg(&request.uuid, request.headers));
     let res = handle.await.unwrap();
@@ -407,7 +1186,25 @@ async fn hander_send_msg(Json(request): Json<SendMsgRequest>) -> impl IntoRespon
 }
 
 /// SendEvent sends event to FreeSWITCH.
-async fn hander_send_event(Json(request): Json<SendEventRequest>) -> impl IntoResponse {
+#[utoipa::path(
+    post,
+    path = "/api/send/event",
+    tag = "command",
+    request_body = SendEventRequest,
+    responses(
+        (status = 200, description = "OK", body = Reply),
+        (status = 400, description = "Invalid request", body = Reply),
+        Rejections,
+    )
+)]
+async fn hander_send_event(
+    axum::Extension(identity): axum::Extension<Identity>,
+    ApiJson(request): ApiJson<SendEventRequest>,
+) -> impl IntoResponse {
+    let (policy_cmd, policy_args) = crate::policy::sendevent_command(request.event_id, &request.subclass_name);
+    if let Some(response) = denied(&identity, &policy_cmd, &policy_args) {
+        return response;
+    }
     let handle = tokio::task::spawn_blocking(move || {
         switch_sys::sendevent(
             request.event_id,
@@ -432,7 +1229,30 @@ async fn hander_send_event(Json(request): Json<SendEventRequest>) -> impl IntoRe
 }
 
 /// Command sends a single command to the server and returns a response Event.
-async fn hander_command(Json(request): Json<CommandRequest>) -> impl IntoResponse {
+#[utoipa::path(
+    post,
+    path = "/api/command",
+    tag = "command",
+    request_body = CommandRequest,
+    responses(
+        (status = 200, description = "OK", body = Reply),
+        (status = 400, description = "Invalid request", body = Reply),
+        (status = 409, description = "mod_rustit cannot be unloaded", body = Reply),
+        Rejections,
+    )
+)]
+async fn hander_command(
+    axum::Extension(identity): axum::Extension<Identity>,
+    ApiJson(request): ApiJson<CommandRequest>,
+) -> impl IntoResponse {
+    command(&identity, request).await
+}
+
+/// Run an api command, shared by the HTTP and WebSocket handlers.
+async fn command(identity: &Identity, request: CommandRequest) -> ApiResponse<serde_json::Value> {
+    if let Some(response) = denied(identity, &request.command, &request.args) {
+        return response;
+    }
     let mut cmd = request.command;
     let mut args = request.args;
 
@@ -446,7 +1266,7 @@ async fn hander_command(Json(request): Json<CommandRequest>) -> impl IntoRespons
         args = String::from("reload mod_rustit");
     } else if cmd.contains("unload") && args.contains("mod_rustit") {
         return ApiResponse {
-            code: 501,
+            code: 409,
             message: String::from("Module mod_rustit is in use, cannot unload"),
             data: None,
         };
@@ -482,99 +1302,1618 @@ async fn hander_command(Json(request): Json<CommandRequest>) -> impl IntoRespons
     }
 }
 
-async fn hander_event(
-    Json(request): Json<SubscribeRequest>,
-) -> axum::response::Sse<impl Stream<Item = Result<axum::response::sse::Event, String>>> {
-    let tx = GOLOBAS.ev_tx.lock().unwrap().clone().unwrap();
-    let rx = tx.subscribe();
-    let mut topics: Vec<Topic> = Vec::new();
-    for topic in request.topics {
-        let name = topic.name.to_ascii_uppercase();
-        let id = switch_sys::switch_event_types_t::from_str(&name);
-        if let Some(id) = id {
-            topics.push(Topic {
-                id: Some(id.0),
-                subclass_name: topic.subclass_name,
-                name,
-            });
-        }
+/// List cached storage files
+#[utoipa::path(
+    post,
+    path = "/api/storage/list",
+    tag = "storage",
+    request_body = StorageListRequest,
+    responses(
+        (status = 200, description = "OK", body = Reply),
+        (status = 400, description = "Invalid request", body = Reply),
+        Rejections,
+    )
+)]
+async fn hander_storage_list(
+    axum::Extension(identity): axum::Extension<Identity>,
+    ApiJson(request): ApiJson<StorageListRequest>,
+) -> impl IntoResponse {
+    let args = format!("list {} {}", request.profile, request.prefix);
+    if let Some(response) = denied(&identity, "storage", &args) {
+        return response;
     }
-    let stream = tokio_stream::wrappers::BroadcastStream::new(rx).map(move |result| {
-        result
-            .map(|e: switch_sys::Event| {
-                let mut pass = false;
-                for topic in &topics {
-                    match topic.id {
-                        Some(event_id) => {
-                            if event_id == switch_sys::switch_event_types_t::SWITCH_EVENT_ALL.0 {
-                                pass = true;
-                                break;
-                            } else if (event_id
-                                == switch_sys::switch_event_types_t::SWITCH_EVENT_CUSTOM.0)
-                                && (topic.subclass_name == e.subclass_name)
-                            {
-                                pass = true;
-                                break;
-                            } else if event_id == e.event_id {
-                                pass = true;
-                                break;
-                            }
-                        }
-                        None => {
-                            continue;
-                        }
-                    }
-                }
-                if pass {
-                    Event::default().data(e.json())
-                } else {
-                    Event::default().data("")
-                }
-            })
-            .map_err(|_| "".to_string())
+    let handle = tokio::task::spawn_blocking(move || {
+        crate::storage::cache_list(&request.profile, &request.prefix)
     });
-    axum::response::Sse::new(stream)
+    let res = handle.await.unwrap();
+    match res {
+        Err(e) => ApiResponse {
+            code: 500,
+            message: e,
+            data: None,
+        },
+        Ok(entries) => ApiResponse {
+            code: 200,
+            message: "OK".to_string(),
+            data: Some(serde_json::json!(entries)),
+        },
+    }
 }
 
-#[tokio::main]
-async fn tokio_main(address: String) {
-    let (tx, mut rx) = broadcast::channel::<switch_sys::Event>(64);
-    let (done_tx, mut done_rx) = mpsc::channel(1);
-
-    *GOLOBAS.done_tx.lock().unwrap() = Some(done_tx);
-    *GOLOBAS.ev_tx.lock().unwrap() = Some(tx.clone());
-
-    let f = async move {
-        let _ = done_rx.recv().await;
-        debug!("HTTP API server done");
-    };
-
-    tokio::spawn(async move {
-        loop {
-            let ret = rx.recv().await;
-            match ret {
-                Ok(_) => {}
-                Err(e) => {
-                    error!("{}", e);
-                    break;
-                }
-            }
-        }
+/// Purge cached storage files
+#[utoipa::path(
+    post,
+    path = "/api/storage/purge",
+    tag = "storage",
+    request_body = StoragePurgeRequest,
+    responses(
+        (status = 200, description = "OK", body = Reply),
+        (status = 400, description = "Invalid request", body = Reply),
+        Rejections,
+    )
+)]
+async fn hander_storage_purge(
+    axum::Extension(identity): axum::Extension<Identity>,
+    ApiJson(request): ApiJson<StoragePurgeRequest>,
+) -> impl IntoResponse {
+    let args = format!("purge {} {}", request.profile, request.url);
+    if let Some(response) = denied(&identity, "storage", &args) {
+        return response;
+    }
+    let handle = tokio::task::spawn_blocking(move || {
+        crate::storage::cache_purge(&request.profile, &request.url, request.prefix)
     });
-    debug!("Start HTTP API service {}", address);
-    // build our application with a route
-    let app = Router::new()
-        .route("/api/reloadxml", post(hander_reload_xml))
-        .route("/api/reloadacl", post(hander_reload_acl))
-        .route("/api/module/reload", post(hander_reload_mod))
+    let res = handle.await.unwrap();
+    match res {
+        Err(e) => ApiResponse {
+            code: 500,
+            message: e,
+            data: None,
+        },
+        Ok(count) => ApiResponse {
+            code: 200,
+            message: "OK".to_string(),
+            data: Some(serde_json::json!({ "purged": count })),
+        },
+    }
+}
+
+/// Prefetch storage files into the cache
+#[utoipa::path(
+    post,
+    path = "/api/storage/prefetch",
+    tag = "storage",
+    request_body = StoragePrefetchRequest,
+    responses(
+        (status = 200, description = "OK", body = Reply),
+        (status = 400, description = "Invalid request", body = Reply),
+        Rejections,
+    )
+)]
+async fn hander_storage_prefetch(
+    axum::Extension(identity): axum::Extension<Identity>,
+    ApiJson(request): ApiJson<StoragePrefetchRequest>,
+) -> impl IntoResponse {
+    let args = format!("prefetch {} {}", request.profile, request.urls.join(","));
+    if let Some(response) = denied(&identity, "storage", &args) {
+        return response;
+    }
+    let handle = tokio::task::spawn_blocking(move || {
+        crate::storage::cache_prefetch(&request.profile, &request.urls)
+    });
+    let res = handle.await.unwrap();
+    match res {
+        Err(e) => ApiResponse {
+            code: 500,
+            message: e,
+            data: None,
+        },
+        Ok(failed) => ApiResponse {
+            code: 200,
+            message: "OK".to_string(),
+            data: Some(serde_json::json!({ "failed": failed })),
+        },
+    }
+}
+
+/// Re-upload unsynchronized recordings
+#[utoipa::path(
+    post,
+    path = "/api/storage/resync",
+    tag = "storage",
+    request_body = StorageResyncRequest,
+    responses(
+        (status = 200, description = "OK", body = Reply),
+        (status = 400, description = "Invalid request", body = Reply),
+        Rejections,
+    )
+)]
+async fn hander_storage_resync(
+    axum::Extension(identity): axum::Extension<Identity>,
+    ApiJson(request): ApiJson<StorageResyncRequest>,
+) -> impl IntoResponse {
+    let args = format!("resync {}", request.profile);
+    if let Some(response) = denied(&identity, "storage", &args) {
+        return response;
+    }
+    let handle =
+        tokio::task::spawn_blocking(move || crate::storage::cache_resync(&request.profile));
+    let res = handle.await.unwrap();
+    match res {
+        Err(e) => ApiResponse {
+            code: 500,
+            message: e,
+            data: None,
+        },
+        Ok(count) => ApiResponse {
+            code: 200,
+            message: "OK".to_string(),
+            data: Some(serde_json::json!({ "queued": count })),
+        },
+    }
+}
+
+fn sound_error<T>(e: crate::sounds::Error) -> ApiResponse<T> {
+    ApiResponse {
+        code: e.code(),
+        message: e.to_string(),
+        data: None,
+    }
+}
+
+/// Stream the file parts of an upload into `${temp_dir}`, checking them on the way.
+async fn receive(
+    multipart: &mut axum::extract::Multipart,
+) -> Result<Vec<crate::sounds::Staged>, crate::sounds::Error> {
+    use crate::sounds::{Error, Staged};
+    use tokio::io::AsyncWriteExt;
+    let max_size = crate::sounds::max_size();
+    let mut staged = Vec::new();
+    loop {
+        let field = multipart
+            .next_field()
+            .await
+            .map_err(|e| Error::Invalid(format!("-ERR {}", e.body_text())))?;
+        let mut field = match field {
+            Some(field) => field,
+            None => break,
+        };
+        let name = match field.file_name() {
+            Some(name) => name.to_string(),
+            // Not a file
+            None => continue,
+        };
+        crate::sounds::check(&name)?;
+        let upload = Staged::new(&name);
+        let mut file = tokio::fs::File::create(&upload.path)
+            .await
+            .map_err(|e| Error::Failed(format!("-ERR {}", e)))?;
+        let mut size = 0;
+        while let Some(chunk) = field
+            .chunk()
+            .await
+            .map_err(|e| Error::Invalid(format!("-ERR {}", e.body_text())))?
+        {
+            size += chunk.len() as u64;
+            if size > max_size {
+                return Err(Error::TooLarge(format!(
+                    "-ERR {} is larger than {} bytes",
+                    name, max_size
+                )));
+            }
+            file.write_all(&chunk)
+                .await
+                .map_err(|e| Error::Failed(format!("-ERR {}", e)))?;
+        }
+        file.flush().await.map_err(|e| Error::Failed(format!("-ERR {}", e)))?;
+        staged.push(upload);
+    }
+    if staged.is_empty() {
+        return Err(Error::Invalid(String::from("-ERR No file in the upload")));
+    }
+    Ok(staged)
+}
+
+/// List the files of a sounds directory
+#[utoipa::path(
+    get,
+    path = "/api/sounds/{dir}",
+    tag = "sounds",
+    params(("dir" = String, Path, description = "Sounds directory name"), SoundListQuery),
+    responses(
+        (status = 200, description = "OK", body = Reply),
+        (status = 400, description = "Invalid request", body = Reply),
+        (status = 404, description = "No such directory", body = Reply),
+        Rejections,
+    )
+)]
+async fn hander_sound_list(
+    axum::Extension(identity): axum::Extension<Identity>,
+    ApiPath(dir): ApiPath<String>,
+    ApiQuery(query): ApiQuery<SoundListQuery>,
+) -> impl IntoResponse {
+    let args = format!("list {} {}", dir, query.path);
+    if let Some(response) = denied(&identity, "sounds", &args) {
+        return response;
+    }
+    let handle = tokio::task::spawn_blocking(move || crate::sounds::list(&dir, &query.path));
+    let res = handle.await.unwrap();
+    match res {
+        Err(e) => sound_error(e),
+        Ok(files) => ApiResponse {
+            code: 200,
+            message: "OK".to_string(),
+            data: Some(serde_json::json!(files)),
+        },
+    }
+}
+
+/// Upload files into a sounds directory, `path` in the reply is what to play
+#[utoipa::path(
+    post,
+    path = "/api/sounds/{dir}",
+    tag = "sounds",
+    params(("dir" = String, Path, description = "Sounds directory name"), SoundUploadQuery),
+    request_body(content = String, content_type = "multipart/form-data", description = "One or more file parts"),
+    responses(
+        (status = 200, description = "OK", body = Reply),
+        (status = 400, description = "Invalid request", body = Reply),
+        (status = 404, description = "No such directory", body = Reply),
+        (status = 409, description = "The file exists", body = Reply),
+        (status = 413, description = "File too large", body = Reply),
+        Rejections,
+    )
+)]
+async fn hander_sound_upload(
+    axum::Extension(identity): axum::Extension<Identity>,
+    ApiPath(dir): ApiPath<String>,
+    ApiQuery(query): ApiQuery<SoundUploadQuery>,
+    ApiMultipart(mut multipart): ApiMultipart,
+) -> impl IntoResponse {
+    let args = format!("upload {} {}", dir, query.path);
+    if let Some(response) = denied(&identity, "sounds", &args) {
+        return response;
+    }
+    let staged = match receive(&mut multipart).await {
+        Ok(staged) => staged,
+        Err(e) => return sound_error(e),
+    };
+    let handle = tokio::task::spawn_blocking(move || {
+        staged
+            .iter()
+            .map(|upload| crate::sounds::store(&dir, &query.path, upload, query.rate, query.overwrite))
+            .collect::<Result<Vec<_>, _>>()
+    });
+    let res = handle.await.unwrap();
+    match res {
+        Err(e) => sound_error(e),
+        Ok(files) => ApiResponse {
+            code: 200,
+            message: "OK".to_string(),
+            data: Some(serde_json::json!(files)),
+        },
+    }
+}
+
+/// Download a file of a sounds directory
+#[utoipa::path(
+    get,
+    path = "/api/sounds/{dir}/{name}",
+    tag = "sounds",
+    params(
+        ("dir" = String, Path, description = "Sounds directory name"),
+        ("name" = String, Path, description = "File name, may include sub directories"),
+    ),
+    responses(
+        (status = 200, description = "The file", content_type = "application/octet-stream"),
+        (status = 400, description = "Invalid request", body = Reply),
+        (status = 401, description = "Unauthorized", body = Reply),
+        (status = 403, description = "Forbidden", body = Reply),
+        (status = 404, description = "No such file", body = Reply),
+        (status = 429, description = "Too many requests", body = Reply),
+    )
+)]
+async fn hander_sound_download(
+    axum::Extension(identity): axum::Extension<Identity>,
+    ApiPath((dir, name)): ApiPath<(String, String)>,
+) -> axum::response::Response {
+    use futures::StreamExt;
+    if let Some(response) = denied::<()>(&identity, "sounds", &format!("download {} {}", dir, name)) {
+        return response.into_response();
+    }
+    let path = match crate::sounds::find(&dir, &name) {
+        Ok(path) => path,
+        Err(e) => return sound_error::<()>(e).into_response(),
+    };
+    let file = match tokio::fs::File::open(&path).await {
+        Ok(file) => file,
+        Err(e) => {
+            return ApiResponse::rejection(StatusCode::INTERNAL_SERVER_ERROR, format!("-ERR {}", e))
+                .into_response()
+        }
+    };
+    let stream = tokio_util::codec::FramedRead::new(file, tokio_util::codec::BytesCodec::new())
+        .map(|chunk| chunk.map(|bytes| bytes.freeze()));
+    let mime = mime_guess::from_path(&path).first_or_octet_stream();
+    (
+        [(axum::http::header::CONTENT_TYPE, mime.to_string())],
+        axum::body::Body::from_stream(stream),
+    )
+        .into_response()
+}
+
+/// Delete a file of a sounds directory
+#[utoipa::path(
+    delete,
+    path = "/api/sounds/{dir}/{name}",
+    tag = "sounds",
+    params(
+        ("dir" = String, Path, description = "Sounds directory name"),
+        ("name" = String, Path, description = "File name, may include sub directories"),
+    ),
+    responses(
+        (status = 200, description = "OK", body = Reply),
+        (status = 400, description = "Invalid request", body = Reply),
+        (status = 404, description = "No such file", body = Reply),
+        Rejections,
+    )
+)]
+async fn hander_sound_delete(
+    axum::Extension(identity): axum::Extension<Identity>,
+    ApiPath((dir, name)): ApiPath<(String, String)>,
+) -> impl IntoResponse {
+    let args = format!("delete {} {}", dir, name);
+    if let Some(response) = denied(&identity, "sounds", &args) {
+        return response;
+    }
+    let handle = tokio::task::spawn_blocking(move || crate::sounds::delete(&dir, &name));
+    let res = handle.await.unwrap();
+    match res {
+        Err(e) => sound_error(e),
+        Ok(()) => ApiResponse {
+            code: 200,
+            message: "OK".to_string(),
+            data: Some(()),
+        },
+    }
+}
+
+/// Upload files into a storage profile cache, they are sent on to
+/// the storage server and play as `profile://path` right away
+#[utoipa::path(
+    post,
+    path = "/api/storage/{profile}/upload",
+    tag = "storage",
+    params(("profile" = String, Path, description = "Storage profile name"), SoundUploadQuery),
+    request_body(content = String, content_type = "multipart/form-data", description = "One or more file parts"),
+    responses(
+        (status = 200, description = "OK", body = Reply),
+        (status = 400, description = "Invalid request", body = Reply),
+        (status = 404, description = "No such storage profile", body = Reply),
+        (status = 413, description = "File too large", body = Reply),
+        Rejections,
+    )
+)]
+async fn hander_storage_upload(
+    axum::Extension(identity): axum::Extension<Identity>,
+    ApiPath(profile): ApiPath<String>,
+    ApiQuery(query): ApiQuery<SoundUploadQuery>,
+    ApiMultipart(mut multipart): ApiMultipart,
+) -> impl IntoResponse {
+    let args = format!("upload {} {}", profile, query.path);
+    if let Some(response) = denied(&identity, "storage", &args) {
+        return response;
+    }
+    let staged = match receive(&mut multipart).await {
+        Ok(staged) => staged,
+        Err(e) => return sound_error(e),
+    };
+    let handle = tokio::task::spawn_blocking(move || {
+        staged
+            .iter()
+            .map(|upload| crate::sounds::store_storage(&profile, &query.path, upload, query.rate))
+            .collect::<Result<Vec<_>, _>>()
+    });
+    let res = handle.await.unwrap();
+    match res {
+        Err(e) => sound_error(e),
+        Ok(files) => ApiResponse {
+            code: 200,
+            message: "OK".to_string(),
+            data: Some(serde_json::json!(files)),
+        },
+    }
+}
+
+/// Mint a short lived token for the `token` query parameter of
+/// `/api/sse/event` and `/api/ws`, it carries the scope and policy of the caller
+#[utoipa::path(
+    post,
+    path = "/api/token",
+    tag = "auth",
+    request_body = TokenRequest,
+    responses(
+        (status = 200, description = "OK", body = Reply),
+        (status = 400, description = "Invalid request", body = Reply),
+        (status = 401, description = "Unauthorized", body = Reply),
+        (status = 429, description = "Too many requests", body = Reply),
+        (status = 500, description = "Failed", body = Reply),
+    )
+)]
+async fn hander_token(
+    axum::Extension(identity): axum::Extension<Identity>,
+    request: Option<ApiJson<TokenRequest>>,
+) -> impl IntoResponse {
+    let request = request.map(|ApiJson(request)| request).unwrap_or_default();
+    let (secret, max_ttl) = {
+        let profile = GOLOBAS.profile.lock().unwrap();
+        (profile.token_secret.clone(), profile.token_ttl)
+    };
+    let ttl = match request.ttl {
+        0 => max_ttl,
+        ttl => ttl.min(max_ttl),
+    };
+    let mut claims = crate::token::Claims::new(&identity.name, TOKEN_AUDIENCE, ttl);
+    claims.scope = identity.scope.as_str().to_string();
+    claims.policy = identity.policy.clone();
+    match crate::token::mint(&secret, &claims) {
+        Err(e) => ApiResponse {
+            code: 500,
+            message: e,
+            data: None,
+        },
+        Ok(token) => ApiResponse {
+            code: 200,
+            message: "OK".to_string(),
+            data: Some(serde_json::json!({ "token": token, "expires": claims.exp, "ttl": ttl })),
+        },
+    }
+}
+
+/// Start a command in the background, the result comes as a
+/// BACKGROUND_JOB event, a webhook or from `GET /api/jobs/{id}`.
+#[utoipa::path(
+    post,
+    path = "/api/jobs",
+    tag = "jobs",
+    request_body = JobRequest,
+    responses(
+        (status = 200, description = "OK", body = Reply),
+        (status = 400, description = "Invalid request", body = Reply),
+        Rejections,
+    )
+)]
+async fn hander_job_submit(
+    axum::Extension(identity): axum::Extension<Identity>,
+    ApiJson(request): ApiJson<JobRequest>,
+) -> impl IntoResponse {
+    if let Some(response) = denied(&identity, &request.command, &request.args) {
+        return response;
+    }
+    // Held by the job until its command finishes.
+    let permit = match GOLOBAS.limits.lock().unwrap().acquire() {
+        Some(permit) => permit,
+        None => {
+            return ApiResponse {
+                code: 429,
+                message: String::from("-ERR Too many concurrent requests"),
+                data: None,
+            }
+        }
+    };
+    let handle = tokio::task::spawn_blocking(move || {
+        crate::jobs::submit(
+            &identity.name,
+            &request.command,
+            &request.args,
+            &request.webhook,
+            permit,
+        )
+    });
+    let res = handle.await.unwrap();
+    match res {
+        Err(e) => ApiResponse {
+            code: 500,
+            message: e,
+            data: None,
+        },
+        Ok(job) => ApiResponse {
+            code: 200,
+            message: "OK".to_string(),
+            data: Some(serde_json::json!(job)),
+        },
+    }
+}
+
+/// Poll a background job
+#[utoipa::path(
+    get,
+    path = "/api/jobs/{id}",
+    tag = "jobs",
+    params(("id" = String, Path, description = "Job UUID")),
+    responses(
+        (status = 200, description = "OK", body = Reply),
+        (status = 400, description = "Invalid request", body = Reply),
+        (status = 404, description = "No such job", body = Reply),
+        Rejections,
+    )
+)]
+async fn hander_job_get(
+    axum::Extension(identity): axum::Extension<Identity>,
+    ApiPath(id): ApiPath<String>,
+) -> impl IntoResponse {
+    match crate::jobs::get(&id, &identity.name) {
+        Some(job) => ApiResponse {
+            code: 200,
+            message: "OK".to_string(),
+            data: Some(serde_json::json!(job)),
+        },
+        None => ApiResponse {
+            code: 404,
+            message: format!("-ERR No such job [{}]", id),
+            data: None,
+        },
+    }
+}
+
+/// System status
+#[utoipa::path(
+    get,
+    path = "/api/status",
+    tag = "inventory",
+    responses(
+        (status = 200, description = "OK", body = Reply),
+        Rejections,
+    )
+)]
+async fn hander_status(axum::Extension(identity): axum::Extension<Identity>) -> impl IntoResponse {
+    if let Some(response) = denied(&identity, "status", "") {
+        return response;
+    }
+    let handle = tokio::task::spawn_blocking(crate::inventory::status);
+    let status = handle.await.unwrap();
+    ApiResponse {
+        code: 200,
+        message: "OK".to_string(),
+        data: Some(status),
+    }
+}
+
+/// Prometheus metrics
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "system",
+    responses(
+        (status = 200, description = "Prometheus text format", body = String, content_type = "text/plain"),
+        (status = 401, description = "Unauthorized", body = Reply),
+    )
+)]
+async fn hander_metrics() -> impl IntoResponse {
+    let handle = tokio::task::spawn_blocking(|| {
+        let mut events = Vec::new();
+        if let Some(api) = GOLOBAS.events.lock().unwrap().as_ref() {
+            events.push(("api", api.stats()));
+        }
+        if let Some(grpc) = crate::grcp::event_stats() {
+            events.push(("grpc", grpc));
+        }
+        crate::metrics::render(&switch_sys::status(), &events)
+    });
+    let text = handle.await.unwrap();
+    (
+        [(
+            axum::http::header::CONTENT_TYPE,
+            "text/plain; version=0.0.4; charset=utf-8",
+        )],
+        text,
+    )
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        hander_reload_xml,
+        hander_reload_acl,
+        hander_reload_mod,
+        hander_load_mod,
+        hander_unload_mod,
+        hander_jsapi,
+        hander_send_event,
+        hander_send_msg,
+        hander_command,
+        hander_storage_list,
+        hander_storage_purge,
+        hander_storage_prefetch,
+        hander_storage_resync,
+        hander_storage_upload,
+        hander_sound_list,
+        hander_sound_upload,
+        hander_sound_download,
+        hander_sound_delete,
+        hander_call_originate,
+        hander_call_answer,
+        hander_call_hangup,
+        hander_call_transfer,
+        hander_call_bridge,
+        hander_call_park,
+        hander_call_hold,
+        hander_call_play,
+        hander_call_record,
+        hander_call_dtmf,
+        hander_call_set_variable,
+        hander_call_get_variable,
+        hander_job_submit,
+        hander_job_get,
+        hander_status,
+        hander_metrics,
+        hander_channels,
+        hander_calls,
+        hander_registrations,
+        hander_modules,
+        hander_gateways,
+        hander_event,
+        hander_ws,
+        hander_token,
+    ),
+    components(schemas(
+        Reply,
+        ModuleRequest,
+        JSAPIRequest,
+        SendEventRequest,
+        SendMsgRequest,
+        CommandRequest,
+        JobRequest,
+        Topic,
+        SubscribeRequest,
+        TokenRequest,
+        StorageListRequest,
+        StoragePurgeRequest,
+        StoragePrefetchRequest,
+        StorageResyncRequest,
+        crate::sounds::SoundFile,
+        crate::filter::HeaderFilter,
+        crate::call::Originate,
+        crate::call::Channel,
+        crate::call::Hangup,
+        crate::call::Transfer,
+        crate::call::Bridge,
+        crate::call::Hold,
+        crate::call::Play,
+        crate::call::Record,
+        crate::call::Dtmf,
+        crate::call::SetVariable,
+        crate::call::GetVariable,
+    )),
+    modifiers(&SecuritySchemes),
+    security(("bearer" = []), ("basic" = [])),
+)]
+struct ApiDoc;
+
+struct SecuritySchemes;
+
+impl utoipa::Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        use utoipa::openapi::security::{Http, HttpAuthScheme, SecurityScheme};
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)),
+        );
+        components.add_security_scheme(
+            "basic",
+            SecurityScheme::Http(Http::new(HttpAuthScheme::Basic)),
+        );
+    }
+}
+
+/// The OpenAPI document of this API
+async fn hander_openapi() -> impl IntoResponse {
+    Json(ApiDoc::openapi())
+}
+
+/// Unknown routes
+async fn hander_not_found() -> impl IntoResponse {
+    ApiResponse::rejection(StatusCode::NOT_FOUND, StatusCode::NOT_FOUND.to_string())
+}
+
+/// Run an inventory listing off the runtime.
+async fn inventory<F>(
+    params: std::collections::HashMap<String, String>,
+    f: F,
+) -> ApiResponse<serde_json::Value>
+where
+    F: FnOnce(&crate::inventory::Query) -> Result<serde_json::Value, String> + Send + 'static,
+{
+    let query = crate::inventory::Query::from_params(params);
+    let handle = tokio::task::spawn_blocking(move || f(&query));
+    let res = handle.await.unwrap();
+    match res {
+        Err(e) => ApiResponse {
+            code: 500,
+            message: e,
+            data: None,
+        },
+        Ok(page) => ApiResponse {
+            code: 200,
+            message: "OK".to_string(),
+            data: Some(page),
+        },
+    }
+}
+
+/// Active channels
+#[utoipa::path(
+    get,
+    path = "/api/channels",
+    tag = "inventory",
+    params(crate::inventory::Query),
+    responses(
+        (status = 200, description = "OK", body = Reply),
+        (status = 400, description = "Invalid request", body = Reply),
+        Rejections,
+    )
+)]
+async fn hander_channels(
+    axum::Extension(identity): axum::Extension<Identity>,
+    ApiQuery(params): ApiQuery<std::collections::HashMap<String, String>>,
+) -> impl IntoResponse {
+    if let Some(response) = denied(&identity, "show", "channels") {
+        return response;
+    }
+    inventory(params, crate::inventory::channels).await
+}
+
+/// Bridged calls
+#[utoipa::path(
+    get,
+    path = "/api/calls",
+    tag = "inventory",
+    params(crate::inventory::Query),
+    responses(
+        (status = 200, description = "OK", body = Reply),
+        (status = 400, description = "Invalid request", body = Reply),
+        Rejections,
+    )
+)]
+async fn hander_calls(
+    axum::Extension(identity): axum::Extension<Identity>,
+    ApiQuery(params): ApiQuery<std::collections::HashMap<String, String>>,
+) -> impl IntoResponse {
+    if let Some(response) = denied(&identity, "show", "calls") {
+        return response;
+    }
+    inventory(params, crate::inventory::calls).await
+}
+
+/// SIP registrations
+#[utoipa::path(
+    get,
+    path = "/api/registrations",
+    tag = "inventory",
+    params(crate::inventory::Query),
+    responses(
+        (status = 200, description = "OK", body = Reply),
+        (status = 400, description = "Invalid request", body = Reply),
+        Rejections,
+    )
+)]
+async fn hander_registrations(
+    axum::Extension(identity): axum::Extension<Identity>,
+    ApiQuery(params): ApiQuery<std::collections::HashMap<String, String>>,
+) -> impl IntoResponse {
+    if let Some(response) = denied(&identity, "show", "registrations") {
+        return response;
+    }
+    inventory(params, crate::inventory::registrations).await
+}
+
+/// Loaded modules
+#[utoipa::path(
+    get,
+    path = "/api/modules",
+    tag = "inventory",
+    params(crate::inventory::Query),
+    responses(
+        (status = 200, description = "OK", body = Reply),
+        (status = 400, description = "Invalid request", body = Reply),
+        Rejections,
+    )
+)]
+async fn hander_modules(
+    axum::Extension(identity): axum::Extension<Identity>,
+    ApiQuery(params): ApiQuery<std::collections::HashMap<String, String>>,
+) -> impl IntoResponse {
+    if let Some(response) = denied(&identity, "show", "modules") {
+        return response;
+    }
+    inventory(params, crate::inventory::modules).await
+}
+
+/// Sofia gateways
+#[utoipa::path(
+    get,
+    path = "/api/gateways",
+    tag = "inventory",
+    params(crate::inventory::Query),
+    responses(
+        (status = 200, description = "OK", body = Reply),
+        (status = 400, description = "Invalid request", body = Reply),
+        Rejections,
+    )
+)]
+async fn hander_gateways(
+    axum::Extension(identity): axum::Extension<Identity>,
+    ApiQuery(params): ApiQuery<std::collections::HashMap<String, String>>,
+) -> impl IntoResponse {
+    if let Some(response) = denied(&identity, "sofia", "xmlstatus gateway") {
+        return response;
+    }
+    inventory(params, crate::inventory::gateways).await
+}
+
+/// The call control client of an identity.
+fn call_client(identity: &Identity) -> crate::call::Client {
+    crate::call::Client {
+        name: identity.name.clone(),
+        policy: identity.policy.clone(),
+    }
+}
+
+/// Run a call control function off the runtime.
+async fn call_control<F>(f: F) -> ApiResponse<serde_json::Value>
+where
+    F: FnOnce() -> Result<serde_json::Value, crate::call::Error> + Send + 'static,
+{
+    let handle = tokio::task::spawn_blocking(f);
+    let res = handle.await.unwrap();
+    match res {
+        Err(e) => ApiResponse {
+            code: e.code(),
+            message: e.to_string(),
+            data: None,
+        },
+        Ok(data) => ApiResponse {
+            code: 200,
+            message: "OK".to_string(),
+            data: Some(data),
+        },
+    }
+}
+
+/// Place a new call
+#[utoipa::path(
+    post,
+    path = "/api/call/originate",
+    tag = "call",
+    request_body = crate::call::Originate,
+    responses(
+        (status = 200, description = "OK", body = Reply),
+        (status = 400, description = "Invalid request", body = Reply),
+        Rejections,
+    )
+)]
+async fn hander_call_originate(
+    axum::Extension(identity): axum::Extension<Identity>,
+    ApiJson(request): ApiJson<crate::call::Originate>,
+) -> impl IntoResponse {
+    let client = call_client(&identity);
+    call_control(move || crate::call::originate(&client, &request)).await
+}
+
+/// Answer a channel
+#[utoipa::path(
+    post,
+    path = "/api/call/answer",
+    tag = "call",
+    request_body = crate::call::Channel,
+    responses(
+        (status = 200, description = "OK", body = Reply),
+        (status = 400, description = "Invalid request", body = Reply),
+        (status = 404, description = "No such channel", body = Reply),
+        Rejections,
+    )
+)]
+async fn hander_call_answer(
+    axum::Extension(identity): axum::Extension<Identity>,
+    ApiJson(request): ApiJson<crate::call::Channel>,
+) -> impl IntoResponse {
+    let client = call_client(&identity);
+    call_control(move || crate::call::answer(&client, &request)).await
+}
+
+/// Hang up a channel
+#[utoipa::path(
+    post,
+    path = "/api/call/hangup",
+    tag = "call",
+    request_body = crate::call::Hangup,
+    responses(
+        (status = 200, description = "OK", body = Reply),
+        (status = 400, description = "Invalid request", body = Reply),
+        (status = 404, description = "No such channel", body = Reply),
+        Rejections,
+    )
+)]
+async fn hander_call_hangup(
+    axum::Extension(identity): axum::Extension<Identity>,
+    ApiJson(request): ApiJson<crate::call::Hangup>,
+) -> impl IntoResponse {
+    let client = call_client(&identity);
+    call_control(move || crate::call::hangup(&client, &request)).await
+}
+
+/// Transfer a channel to another extension
+#[utoipa::path(
+    post,
+    path = "/api/call/transfer",
+    tag = "call",
+    request_body = crate::call::Transfer,
+    responses(
+        (status = 200, description = "OK", body = Reply),
+        (status = 400, description = "Invalid request", body = Reply),
+        (status = 404, description = "No such channel", body = Reply),
+        Rejections,
+    )
+)]
+async fn hander_call_transfer(
+    axum::Extension(identity): axum::Extension<Identity>,
+    ApiJson(request): ApiJson<crate::call::Transfer>,
+) -> impl IntoResponse {
+    let client = call_client(&identity);
+    call_control(move || crate::call::transfer(&client, &request)).await
+}
+
+/// Bridge two channels
+#[utoipa::path(
+    post,
+    path = "/api/call/bridge",
+    tag = "call",
+    request_body = crate::call::Bridge,
+    responses(
+        (status = 200, description = "OK", body = Reply),
+        (status = 400, description = "Invalid request", body = Reply),
+        (status = 404, description = "No such channel", body = Reply),
+        Rejections,
+    )
+)]
+async fn hander_call_bridge(
+    axum::Extension(identity): axum::Extension<Identity>,
+    ApiJson(request): ApiJson<crate::call::Bridge>,
+) -> impl IntoResponse {
+    let client = call_client(&identity);
+    call_control(move || crate::call::bridge(&client, &request)).await
+}
+
+/// Park a channel
+#[utoipa::path(
+    post,
+    path = "/api/call/park",
+    tag = "call",
+    request_body = crate::call::Channel,
+    responses(
+        (status = 200, description = "OK", body = Reply),
+        (status = 400, description = "Invalid request", body = Reply),
+        (status = 404, description = "No such channel", body = Reply),
+        Rejections,
+    )
+)]
+async fn hander_call_park(
+    axum::Extension(identity): axum::Extension<Identity>,
+    ApiJson(request): ApiJson<crate::call::Channel>,
+) -> impl IntoResponse {
+    let client = call_client(&identity);
+    call_control(move || crate::call::park(&client, &request)).await
+}
+
+/// Put a channel on or off hold
+#[utoipa::path(
+    post,
+    path = "/api/call/hold",
+    tag = "call",
+    request_body = crate::call::Hold,
+    responses(
+        (status = 200, description = "OK", body = Reply),
+        (status = 400, description = "Invalid request", body = Reply),
+        (status = 404, description = "No such channel", body = Reply),
+        Rejections,
+    )
+)]
+async fn hander_call_hold(
+    axum::Extension(identity): axum::Extension<Identity>,
+    ApiJson(request): ApiJson<crate::call::Hold>,
+) -> impl IntoResponse {
+    let client = call_client(&identity);
+    call_control(move || crate::call::hold(&client, &request)).await
+}
+
+/// Play a file to a channel
+#[utoipa::path(
+    post,
+    path = "/api/call/play",
+    tag = "call",
+    request_body = crate::call::Play,
+    responses(
+        (status = 200, description = "OK", body = Reply),
+        (status = 400, description = "Invalid request", body = Reply),
+        (status = 404, description = "No such channel", body = Reply),
+        Rejections,
+    )
+)]
+async fn hander_call_play(
+    axum::Extension(identity): axum::Extension<Identity>,
+    ApiJson(request): ApiJson<crate::call::Play>,
+) -> impl IntoResponse {
+    let client = call_client(&identity);
+    call_control(move || crate::call::play(&client, &request)).await
+}
+
+/// Start or stop recording a channel
+#[utoipa::path(
+    post,
+    path = "/api/call/record",
+    tag = "call",
+    request_body = crate::call::Record,
+    responses(
+        (status = 200, description = "OK", body = Reply),
+        (status = 400, description = "Invalid request", body = Reply),
+        (status = 404, description = "No such channel", body = Reply),
+        Rejections,
+    )
+)]
+async fn hander_call_record(
+    axum::Extension(identity): axum::Extension<Identity>,
+    ApiJson(request): ApiJson<crate::call::Record>,
+) -> impl IntoResponse {
+    let client = call_client(&identity);
+    call_control(move || crate::call::record(&client, &request)).await
+}
+
+/// Send DTMF to a channel
+#[utoipa::path(
+    post,
+    path = "/api/call/dtmf",
+    tag = "call",
+    request_body = crate::call::Dtmf,
+    responses(
+        (status = 200, description = "OK", body = Reply),
+        (status = 400, description = "Invalid request", body = Reply),
+        (status = 404, description = "No such channel", body = Reply),
+        Rejections,
+    )
+)]
+async fn hander_call_dtmf(
+    axum::Extension(identity): axum::Extension<Identity>,
+    ApiJson(request): ApiJson<crate::call::Dtmf>,
+) -> impl IntoResponse {
+    let client = call_client(&identity);
+    call_control(move || crate::call::dtmf(&client, &request)).await
+}
+
+/// Set a channel variable
+#[utoipa::path(
+    post,
+    path = "/api/call/setvar",
+    tag = "call",
+    request_body = crate::call::SetVariable,
+    responses(
+        (status = 200, description = "OK", body = Reply),
+        (status = 400, description = "Invalid request", body = Reply),
+        (status = 404, description = "No such channel", body = Reply),
+        Rejections,
+    )
+)]
+async fn hander_call_set_variable(
+    axum::Extension(identity): axum::Extension<Identity>,
+    ApiJson(request): ApiJson<crate::call::SetVariable>,
+) -> impl IntoResponse {
+    let client = call_client(&identity);
+    call_control(move || crate::call::set_variable(&client, &request)).await
+}
+
+/// Get a channel variable
+#[utoipa::path(
+    post,
+    path = "/api/call/getvar",
+    tag = "call",
+    request_body = crate::call::GetVariable,
+    responses(
+        (status = 200, description = "OK", body = Reply),
+        (status = 400, description = "Invalid request", body = Reply),
+        (status = 404, description = "No such channel", body = Reply),
+        Rejections,
+    )
+)]
+async fn hander_call_get_variable(
+    axum::Extension(identity): axum::Extension<Identity>,
+    ApiJson(request): ApiJson<crate::call::GetVariable>,
+) -> impl IntoResponse {
+    let client = call_client(&identity);
+    call_control(move || crate::call::get_variable(&client, &request)).await
+}
+
+/// Resolve topic names to event ids and compile the header filters,
+/// unknown names are dropped.
+fn parse_topics(request: Vec<Topic>) -> Result<Vec<Topic>, String> {
+    let mut topics: Vec<Topic> = Vec::new();
+    for topic in request {
+        let name = topic.name.to_ascii_uppercase();
+        let id = switch_sys::switch_event_types_t::from_str(&name);
+        if let Some(id) = id {
+            let predicates = crate::filter::compile(&topic.filters)?;
+            topics.push(Topic {
+                id: Some(id.0),
+                subclass_name: topic.subclass_name,
+                name,
+                filters: topic.filters,
+                predicates,
+            });
+        }
+    }
+    Ok(topics)
+}
+
+fn topics_match(topics: &[Topic], e: &switch_sys::Event) -> bool {
+    for topic in topics {
+        if !crate::filter::matches_all(&topic.predicates, &e.headers) {
+            continue;
+        }
+        match topic.id {
+            Some(event_id) => {
+                if event_id == switch_sys::switch_event_types_t::SWITCH_EVENT_ALL.0 {
+                    return true;
+                } else if (event_id == switch_sys::switch_event_types_t::SWITCH_EVENT_CUSTOM.0)
+                    && (topic.subclass_name == e.subclass_name)
+                {
+                    return true;
+                } else if event_id == e.event_id {
+                    return true;
+                }
+            }
+            None => {
+                continue;
+            }
+        }
+    }
+    false
+}
+
+/// Resume after `Last-Event-ID`, events that are no longer buffered
+/// are reported as a `gap` event.
+#[utoipa::path(
+    get,
+    path = "/api/sse/event",
+    tag = "events",
+    request_body = SubscribeRequest,
+    params(
+        ("Last-Event-ID" = Option<u64>, Header, description = "Sequence to resume after"),
+        SseQuery,
+    ),
+    responses(
+        (status = 200, description = "Server-sent events", content_type = "text/event-stream"),
+        (status = 400, description = "Invalid request", body = Reply),
+        (status = 401, description = "Unauthorized", body = Reply),
+        (status = 404, description = "No such channel", body = Reply),
+        (status = 503, description = "Events are not available", body = Reply),
+    )
+)]
+async fn hander_event(
+    headers: axum::http::HeaderMap,
+    ApiQuery(query): ApiQuery<SseQuery>,
+    body: axum::body::Bytes,
+) -> Result<
+    axum::response::Sse<impl Stream<Item = Result<axum::response::sse::Event, String>>>,
+    ApiResponse<()>,
+> {
+    let request: SubscribeRequest = match &query.subscribe {
+        Some(subscribe) => serde_json::from_str(subscribe),
+        None => serde_json::from_slice(&body),
+    }
+    .map_err(|e| ApiResponse {
+        code: 400,
+        message: format!("-ERR Invalid subscribe request, {}", e),
+        data: None,
+    })?;
+    let topics = parse_topics(request.topics).map_err(|e| ApiResponse {
+        code: 400,
+        message: e,
+        data: None,
+    })?;
+    let mut calls = None;
+    if !request.uuids.is_empty() {
+        calls = crate::filter::CallTracker::new(&request.uuids);
+        if calls.is_none() {
+            return Err(ApiResponse {
+                code: 404,
+                message: String::from("No such channel"),
+                data: None,
+            });
+        }
+    }
+    let last_event_id = headers
+        .get("last-event-id")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<u64>().ok());
+    // Gone while the module shuts down
+    let events = GOLOBAS.events.lock().unwrap().clone().ok_or_else(|| ApiResponse {
+        code: 503,
+        message: String::from("-ERR Events are not available"),
+        data: None,
+    })?;
+    let subscription = EventSubscription {
+        rx: events.subscribe(last_event_id),
+        topics,
+        headers: request.headers,
+        calls,
+    };
+    let stream = futures::stream::unfold(subscription, |mut subscription| async move {
+        let item = subscription.next().await?;
+        Some((item, subscription))
+    });
+    Ok(axum::response::Sse::new(stream))
+}
+
+struct EventSubscription {
+    rx: Subscriber<switch_sys::Event>,
+    topics: Vec<Topic>,
+    headers: Vec<String>,
+    calls: Option<crate::filter::CallTracker>,
+}
+
+impl EventSubscription {
+    /// The next matching event, `None` ends the stream.
+    async fn next(&mut self) -> Option<Result<Event, String>> {
+        if self.calls.as_ref().is_some_and(|calls| calls.done()) {
+            return None;
+        }
+        loop {
+            match self.rx.recv().await? {
+                Item::Event(seq, mut e) => {
+                    if let Some(calls) = &mut self.calls {
+                        if !calls.track(e.event_id, &e.headers) {
+                            continue;
+                        }
+                        if !self.topics.is_empty() && !topics_match(&self.topics, &e) {
+                            continue;
+                        }
+                    } else if !topics_match(&self.topics, &e) {
+                        continue;
+                    }
+                    crate::filter::project(&mut e.headers, &self.headers);
+                    return Some(Ok(Event::default().id(seq.to_string()).data(e.json())));
+                }
+                Item::Gap(first, last) => {
+                    let gap = serde_json::json!({ "first": first, "last": last });
+                    return Some(Ok(Event::default()
+                        .event("gap")
+                        .id(last.to_string())
+                        .data(gap.to_string())));
+                }
+            }
+        }
+    }
+}
+
+/// A message from a WebSocket client, `id` is echoed back in the reply.
+#[derive(Debug, Deserialize)]
+struct WsRequest {
+    #[serde(default)]
+    id: serde_json::Value,
+    #[serde(flatten)]
+    action: WsAction,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum WsAction {
+    Subscribe { topics: Vec<Topic> },
+    Unsubscribe { topics: Vec<Topic> },
+    Command(CommandRequest),
+    Jsapi(JSAPIRequest),
+}
+
+#[derive(Serialize)]
+struct WsReply {
+    id: serde_json::Value,
+    #[serde(flatten)]
+    response: ApiResponse<serde_json::Value>,
+}
+
+impl WsReply {
+    fn new(id: serde_json::Value, code: i32, message: &str, data: Option<serde_json::Value>) -> WsReply {
+        WsReply {
+            id,
+            response: ApiResponse {
+                code,
+                message: message.to_string(),
+                data,
+            },
+        }
+    }
+
+    fn message(&self) -> axum::extract::ws::Message {
+        let text = serde_json::to_string(self).unwrap_or_default();
+        axum::extract::ws::Message::Text(text)
+    }
+}
+
+/// Events and commands over one socket
+#[utoipa::path(
+    get,
+    path = "/api/ws",
+    tag = "events",
+    params(("token" = Option<String>, Query, description = "Query token from `/api/token`")),
+    responses(
+        (status = 101, description = "WebSocket with `subscribe`, `unsubscribe`, `command` and `jsapi` messages"),
+        (status = 401, description = "Unauthorized", body = Reply),
+    )
+)]
+async fn hander_ws(
+    axum::extract::ConnectInfo(addr): axum::extract::ConnectInfo<std::net::SocketAddr>,
+    axum::Extension(identity): axum::Extension<Identity>,
+    ws: axum::extract::ws::WebSocketUpgrade,
+) -> impl IntoResponse {
+    ws.on_upgrade(move |socket| ws_session(socket, identity, addr.ip().to_string()))
+}
+
+async fn ws_session(mut socket: axum::extract::ws::WebSocket, identity: Identity, remote: String) {
+    let client = client_key(&identity, &remote);
+    use axum::extract::ws::Message;
+
+    let events = match GOLOBAS.events.lock().unwrap().clone() {
+        Some(events) => events,
+        None => return,
+    };
+    let mut rx = events.subscribe(None);
+    let mut topics: Vec<Topic> = Vec::new();
+    // Commands run in their own task, replies come back through here
+    let (reply_tx, mut reply_rx) = mpsc::channel::<WsReply>(16);
+
+    loop {
+        let message = tokio::select! {
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => text,
+                Some(Ok(Message::Close(_))) | None => break,
+                Some(Ok(_)) => continue,
+                Some(Err(e)) => {
+                    debug!("WebSocket [{}] {}", identity.name, e);
+                    break;
+                }
+            },
+            reply = reply_rx.recv() => {
+                if let Some(reply) = reply {
+                    if socket.send(reply.message()).await.is_err() {
+                        break;
+                    }
+                }
+                continue;
+            },
+            event = rx.recv() => {
+                let text = match event {
+                    Some(Item::Event(seq, e)) if topics_match(&topics, &e) => {
+                        format!("{{\"sequence\":{},\"event\":{}}}", seq, e.json())
+                    }
+                    Some(Item::Event(_, _)) => continue,
+                    Some(Item::Gap(_, _)) if topics.is_empty() => continue,
+                    Some(Item::Gap(first, last)) => {
+                        warn!("WebSocket [{}] lost events {}..{}", identity.name, first, last);
+                        serde_json::json!({ "gap": { "first": first, "last": last } }).to_string()
+                    }
+                    None => break,
+                };
+                if socket.send(Message::Text(text)).await.is_err() {
+                    break;
+                }
+                continue;
+            },
+        };
+
+        let request = match serde_json::from_str::<WsRequest>(&message) {
+            Ok(request) => request,
+            Err(e) => {
+                let reply = WsReply::new(serde_json::Value::Null, 400, &e.to_string(), None);
+                if socket.send(reply.message()).await.is_err() {
+                    break;
+                }
+                continue;
+            }
+        };
+
+        let id = request.id;
+        let reply = match request.action {
+            WsAction::Subscribe { topics: request } => match parse_topics(request) {
+                Ok(request) => {
+                    for topic in request {
+                        topics.retain(|t| {
+                            !(t.id == topic.id && t.subclass_name == topic.subclass_name)
+                        });
+                        topics.push(topic);
+                    }
+                    Some(WsReply::new(id, 200, "OK", Some(serde_json::json!(topics))))
+                }
+                Err(e) => Some(WsReply::new(id, 400, &e, None)),
+            },
+            WsAction::Unsubscribe { topics: request } => match parse_topics(request) {
+                Ok(request) => {
+                    for topic in request {
+                        topics.retain(|t| {
+                            !(t.id == topic.id && t.subclass_name == topic.subclass_name)
+                        });
+                    }
+                    Some(WsReply::new(id, 200, "OK", Some(serde_json::json!(topics))))
+                }
+                Err(e) => Some(WsReply::new(id, 400, &e, None)),
+            },
+            _ if !identity.scope.allows(Scope::Command) => Some(WsReply::new(
+                id,
+                403,
+                &StatusCode::FORBIDDEN.to_string(),
+                None,
+            )),
+            WsAction::Command(request) => match admit(&client, true) {
+                Err(e) => Some(WsReply::new(id, e.code, &e.message, None)),
+                Ok(permit) => {
+                    let reply_tx = reply_tx.clone();
+                    let identity = identity.clone();
+                    let remote = remote.clone();
+                    tokio::spawn(async move {
+                        let _permit = permit;
+                        let pending = crate::audit::Pending::new("ws", "command", serde_json::json!(request));
+                        let response = command(&identity, request).await;
+                        pending.finish(&identity.name, &remote, response.code);
+                        let _ = reply_tx.send(WsReply { id, response }).await;
+                    });
+                    None
+                }
+            },
+            WsAction::Jsapi(request) => match admit(&client, true) {
+                Err(e) => Some(WsReply::new(id, e.code, &e.message, None)),
+                Ok(permit) => {
+                    let reply_tx = reply_tx.clone();
+                    let identity = identity.clone();
+                    let remote = remote.clone();
+                    tokio::spawn(async move {
+                        let _permit = permit;
+                        let pending = crate::audit::Pending::new("ws", "jsapi", serde_json::json!(request));
+                        let response = jsapi(&identity, request).await;
+                        pending.finish(&identity.name, &remote, response.code);
+                        let _ = reply_tx.send(WsReply { id, response }).await;
+                    });
+                    None
+                }
+            },
+        };
+        if let Some(reply) = reply {
+            if socket.send(reply.message()).await.is_err() {
+                break;
+            }
+        }
+    }
+    debug!("WebSocket [{}] closed", identity.name);
+}
+
+#[tokio::main]
+async fn tokio_main(address: String) {
+    let profile = GOLOBAS.profile.lock().unwrap().clone();
+    let replay_size = profile.event_replay_size;
+    *GOLOBAS.limits.lock().unwrap() =
+        Limits::new(profile.rate_limit, profile.rate_burst, profile.max_concurrent);
+    let (done_tx, mut done_rx) = mpsc::channel(1);
+
+    *GOLOBAS.done_tx.lock().unwrap() = Some(done_tx);
+    *GOLOBAS.events.lock().unwrap() = Some(Replay::new(replay_size));
+
+    let f = async move {
+        let _ = done_rx.recv().await;
+        debug!("HTTP API server done");
+    };
+
+    debug!("Start HTTP API service {}", address);
+    // build our application with a route
+    let app = Router::new()
+        .route("/api/reloadxml", post(hander_reload_xml))
+        .route("/api/reloadacl", post(hander_reload_acl))
+        .route("/api/module/reload", post(hander_reload_mod))
         .route("/api/module/load", post(hander_load_mod))
         .route("/api/module/unload", post(hander_unload_mod))
         .route("/api/jsapi", post(hander_jsapi))
         .route("/api/send/event", post(hander_send_event))
         .route("/api/send/msg", post(hander_send_msg))
         .route("/api/command", post(hander_command))
+        .route("/api/storage/list", post(hander_storage_list))
+        .route("/api/storage/purge", post(hander_storage_purge))
+        .route("/api/storage/prefetch", post(hander_storage_prefetch))
+        .route("/api/storage/resync", post(hander_storage_resync))
+        // Upload sizes are checked per file against the sounds max-size
+        .route(
+            "/api/storage/:profile/upload",
+            post(hander_storage_upload).layer(axum::extract::DefaultBodyLimit::disable()),
+        )
+        .route(
+            "/api/sounds/:dir",
+            get(hander_sound_list)
+                .post(hander_sound_upload)
+                .layer(axum::extract::DefaultBodyLimit::disable()),
+        )
+        .route(
+            "/api/sounds/:dir/*name",
+            get(hander_sound_download).delete(hander_sound_delete),
+        )
+        .route("/api/call/originate", post(hander_call_originate))
+        .route("/api/call/answer", post(hander_call_answer))
+        .route("/api/call/hangup", post(hander_call_hangup))
+        .route("/api/call/transfer", post(hander_call_transfer))
+        .route("/api/call/bridge", post(hander_call_bridge))
+        .route("/api/call/park", post(hander_call_park))
+        .route("/api/call/hold", post(hander_call_hold))
+        .route("/api/call/play", post(hander_call_play))
+        .route("/api/call/record", post(hander_call_record))
+        .route("/api/call/dtmf", post(hander_call_dtmf))
+        .route("/api/call/setvar", post(hander_call_set_variable))
+        .route("/api/call/getvar", post(hander_call_get_variable))
+        .route("/api/jobs", post(hander_job_submit))
+        .route("/api/jobs/:id", get(hander_job_get))
+        .route("/api/status", get(hander_status))
+        .route("/metrics", get(hander_metrics))
+        .route("/api/channels", get(hander_channels))
+        .route("/api/calls", get(hander_calls))
+        .route("/api/registrations", get(hander_registrations))
+        .route("/api/modules", get(hander_modules))
+        .route("/api/gateways", get(hander_gateways))
         .route("/api/sse/event", get(hander_event))
-        .route_layer(axum::middleware::from_fn(auth_middleware));
+        .route("/api/ws", get(hander_ws))
+        .route("/api/token", post(hander_token))
+        // Outermost last: authenticate, audit, then check scope and limits
+        .route_layer(axum::middleware::from_fn(authorize_middleware))
+        .route_layer(axum::middleware::from_fn(audit_middleware))
+        .route_layer(axum::middleware::from_fn(auth_middleware))
+        .route("/api/openapi.json", get(hander_openapi))
+        .fallback(hander_not_found);
+    // Outermost, so preflight requests are answered before authentication
+    let app = match GOLOBAS.profile.lock().unwrap().cors() {
+        Some(cors) => app.layer(cors),
+        None => app,
+    };
+
+    let profile = GOLOBAS.profile.lock().unwrap().clone();
+    if !profile.tls_cert.is_empty() {
+        let config = crate::tls::server_config(
+            &profile.tls_cert,
+            &profile.tls_key,
+            &profile.tls_client_ca,
+        );
+        let config = match config {
+            Ok(config) => config,
+            Err(e) => {
+                error!("Couldn't load HTTP API TLS config, {}", e);
+                return;
+            }
+        };
+        let addr = match address.parse::<std::net::SocketAddr>() {
+            Ok(addr) => addr,
+            Err(e) => {
+                error!("Unable to parse HTTP API socket address {}, {}", address, e);
+                return;
+            }
+        };
+
+        let handle = axum_server::Handle::new();
+        let shutdown = handle.clone();
+        tokio::spawn(async move {
+            f.await;
+            shutdown.graceful_shutdown(Some(std::time::Duration::from_secs(1)));
+        });
+
+        let ret = axum_server::bind(addr)
+            .acceptor(crate::tls::ClientCertAcceptor::new(config))
+            .handle(handle)
+            .serve(app.into_make_service_with_connect_info::<std::net::SocketAddr>())
+            .await;
+        if let Err(e) = ret {
+            error!("Couldn't start HTTPS API server, {}", e);
+        }
+        return;
+    }
 
     // run our app with hyper, listening globally on port 3000
     let listener = tokio::net::TcpListener::bind(address).await.unwrap();
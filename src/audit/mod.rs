@@ -0,0 +1,356 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::Instant;
+use switch_sys::*;
+
+const EVENT_SUBCLASS: &str = "rustit::audit";
+const MASK: &str = "***";
+
+#[derive(Debug, Clone)]
+struct Profile {
+    enable: bool,
+    file: String,
+    /// Rotate once the file would grow past this many bytes
+    max_size: u64,
+    /// Rotated files kept as `file.1` .. `file.N`
+    max_files: usize,
+    /// Also fire a CUSTOM rustit::audit event
+    event: bool,
+}
+
+impl Profile {
+    fn new() -> Profile {
+        Profile {
+            enable: false,
+            file: String::new(),
+            max_size: 10 * 1024 * 1024,
+            max_files: 5,
+            event: false,
+        }
+    }
+}
+
+struct Log {
+    profile: Profile,
+    file: Option<File>,
+    size: u64,
+}
+
+lazy_static! {
+    static ref GOLOBAS: Mutex<Log> = Mutex::new(Log {
+        profile: Profile::new(),
+        file: None,
+        size: 0,
+    });
+    /// Names whose values are never written out
+    static ref SECRET: Regex = Regex::new(r"(?i)pass|secret|token|auth|credential|key").unwrap();
+    /// `name=value` as in channel variables and dial strings
+    static ref ASSIGNMENT: Regex = Regex::new(r#"([\w.-]+)=([^,;|}\]\s"']*)"#).unwrap();
+    /// `name: "value"` and `"name": "value"`
+    static ref FIELD: Regex = Regex::new(r#""?([\w.-]+)"?\s*:\s*"((?:[^"\\]|\\.)*)""#).unwrap();
+}
+
+/// # Safety
+///
+/// `cfg` must be a valid `rustit.conf` xml node.
+pub unsafe fn load_config(cfg: switch_xml_t) {
+    let mut profile = Profile::new();
+    unsafe {
+        let tmp_str = CString::new("audit").unwrap();
+        let settings_tag = switch_sys::switch_xml_child(cfg, tmp_str.as_ptr());
+        if !settings_tag.is_null() {
+            let tmp_str = CString::new("param").unwrap();
+            let mut param = switch_sys::switch_xml_child(settings_tag, tmp_str.as_ptr());
+            while !param.is_null() {
+                let tmp_str = CString::new("name").unwrap();
+                let var = switch_sys::switch_xml_attr_soft(param, tmp_str.as_ptr());
+                let tmp_str = CString::new("value").unwrap();
+                let val = switch_sys::switch_xml_attr_soft(param, tmp_str.as_ptr());
+
+                let var = switch_sys::switch_to_string(var);
+                let val = switch_sys::switch_to_string(val);
+
+                if var.eq_ignore_ascii_case("enable") {
+                    profile.enable = switch_true(&val);
+                } else if var.eq_ignore_ascii_case("file") {
+                    profile.file = val;
+                } else if var.eq_ignore_ascii_case("max-size") {
+                    // In megabytes
+                    profile.max_size = val.parse::<u64>().unwrap_or(10).max(1) * 1024 * 1024;
+                } else if var.eq_ignore_ascii_case("max-files") {
+                    profile.max_files = val.parse::<usize>().unwrap_or(5);
+                } else if var.eq_ignore_ascii_case("event") {
+                    profile.event = switch_true(&val);
+                }
+                param = (*param).next;
+            }
+        }
+    }
+    if profile.file.is_empty() {
+        profile.file = format!("{}/rustit_audit.log", get_variable("log_dir"));
+    }
+
+    let mut log = GOLOBAS.lock().unwrap();
+    log.file = None;
+    log.size = 0;
+    log.profile = profile;
+}
+
+pub fn enabled() -> bool {
+    GOLOBAS.lock().unwrap().profile.enable
+}
+
+/// Close the log, it is opened again on the next entry.
+pub fn shutdown() {
+    GOLOBAS.lock().unwrap().file = None;
+}
+
+/// One remote operation.
+#[derive(Debug, Clone, Serialize)]
+pub struct Entry {
+    pub timestamp: String,
+    /// `http`, `ws` or `grpc`
+    pub interface: &'static str,
+    pub identity: String,
+    pub remote: String,
+    pub operation: String,
+    pub args: serde_json::Value,
+    /// HTTP status, gRPC errors are mapped to their HTTP equivalent
+    pub code: i32,
+    pub duration_ms: f64,
+}
+
+/// Started when a request comes in, `finish` records it.
+pub struct Pending {
+    interface: &'static str,
+    operation: String,
+    args: serde_json::Value,
+    started: Instant,
+}
+
+impl Pending {
+    pub fn new(interface: &'static str, operation: &str, args: serde_json::Value) -> Pending {
+        Pending {
+            interface,
+            operation: operation.to_string(),
+            args,
+            started: Instant::now(),
+        }
+    }
+
+    pub fn finish(self, identity: &str, remote: &str, code: i32) {
+        if !enabled() {
+            return;
+        }
+        let entry = Entry {
+            timestamp: chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, false),
+            interface: self.interface,
+            identity: identity.to_string(),
+            remote: remote.to_string(),
+            operation: self.operation,
+            args: mask(self.args),
+            code,
+            duration_ms: self.started.elapsed().as_secs_f64() * 1000.0,
+        };
+        record(&entry);
+    }
+}
+
+/// Replace the values of secret looking names, also inside strings.
+pub fn mask(value: serde_json::Value) -> serde_json::Value {
+    use serde_json::Value;
+    match value {
+        Value::Object(object) => Value::Object(
+            object
+                .into_iter()
+                .map(|(name, value)| {
+                    if SECRET.is_match(&name) && !value.is_null() {
+                        (name, Value::String(MASK.to_string()))
+                    } else {
+                        (name, mask(value))
+                    }
+                })
+                .collect(),
+        ),
+        Value::Array(values) => Value::Array(values.into_iter().map(mask).collect()),
+        Value::String(text) => Value::String(mask_text(&text)),
+        value => value,
+    }
+}
+
+/// Mask `name=value` and `name: "value"` pairs of a command line or debug output.
+pub fn mask_text(text: &str) -> String {
+    let text = ASSIGNMENT.replace_all(text, |caps: &regex::Captures| {
+        if SECRET.is_match(&caps[1]) {
+            format!("{}={}", &caps[1], MASK)
+        } else {
+            caps[0].to_string()
+        }
+    });
+    FIELD
+        .replace_all(&text, |caps: &regex::Captures| {
+            if SECRET.is_match(&caps[1]) {
+                caps[0].replace(&format!("\"{}\"", &caps[2]), &format!("\"{}\"", MASK))
+            } else {
+                caps[0].to_string()
+            }
+        })
+        .into_owned()
+}
+
+fn rotate(profile: &Profile) {
+    if profile.max_files == 0 {
+        let _ = fs::remove_file(&profile.file);
+        return;
+    }
+    for i in (1..profile.max_files).rev() {
+        let _ = fs::rename(
+            format!("{}.{}", profile.file, i),
+            format!("{}.{}", profile.file, i + 1),
+        );
+    }
+    let _ = fs::rename(&profile.file, format!("{}.1", profile.file));
+}
+
+pub fn record(entry: &Entry) {
+    let mut log = GOLOBAS.lock().unwrap();
+    if !log.profile.enable {
+        return;
+    }
+    let line = match serde_json::to_string(entry) {
+        Ok(line) => line + "\n",
+        Err(e) => {
+            error!("Audit {}", e);
+            return;
+        }
+    };
+
+    if log.file.is_some() && log.size + line.len() as u64 > log.profile.max_size {
+        log.file = None;
+        rotate(&log.profile);
+    }
+    if log.file.is_none() {
+        let opened = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&log.profile.file)
+            .and_then(|file| file.metadata().map(|metadata| (file, metadata.len())));
+        match opened {
+            Ok((file, size)) => {
+                log.file = Some(file);
+                log.size = size;
+            }
+            Err(e) => {
+                error!("Audit log {} {}", log.profile.file, e);
+            }
+        }
+    }
+    if let Some(file) = log.file.as_mut() {
+        match file.write_all(line.as_bytes()) {
+            Ok(_) => log.size += line.len() as u64,
+            Err(e) => {
+                error!("Audit log {} {}", log.profile.file, e);
+                log.file = None;
+            }
+        }
+    }
+
+    let event = log.profile.event;
+    drop(log);
+
+    if event {
+        let headers = HashMap::from([
+            (String::from("Audit-Interface"), entry.interface.to_string()),
+            (String::from("Audit-Identity"), entry.identity.clone()),
+            (String::from("Audit-Remote"), entry.remote.clone()),
+            (String::from("Audit-Operation"), entry.operation.clone()),
+            (String::from("Audit-Code"), entry.code.to_string()),
+        ]);
+        if let Err(e) = switch_sys::sendevent(
+            switch_event_types_t::SWITCH_EVENT_CUSTOM.0,
+            EVENT_SUBCLASS,
+            headers,
+            line.trim_end(),
+        ) {
+            error!("Audit event {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn secret_fields_are_masked_at_any_depth() {
+        let args = json!({
+            "command": "status",
+            "password": "hunter2",
+            "nested": {"api_key": "abc", "name": "alice", "Auth-Token": 42},
+            "list": [{"secret": "x"}, "plain"],
+            "token": null,
+        });
+        assert_eq!(
+            mask(args),
+            json!({
+                "command": "status",
+                "password": MASK,
+                "nested": {"api_key": MASK, "name": "alice", "Auth-Token": MASK},
+                "list": [{"secret": MASK}, "plain"],
+                "token": null,
+            })
+        );
+    }
+
+    #[test]
+    fn assignments_in_command_lines_are_masked() {
+        assert_eq!(
+            mask_text("{sip_auth_password=s3cret,origination_caller_id_number=1000}sofia/gateway/gw/1001 &park()"),
+            "{sip_auth_password=***,origination_caller_id_number=1000}sofia/gateway/gw/1001 &park()"
+        );
+        assert_eq!(mask_text("status"), "status");
+        assert_eq!(
+            mask(json!("originate {token=abc}user/1000 &echo")),
+            json!("originate {token=***}user/1000 &echo")
+        );
+    }
+
+    #[test]
+    fn debug_and_json_strings_are_masked() {
+        let debug = r#"CommandRequest { command: "status", args: "", password: "hun\"ter2" }"#;
+        assert_eq!(
+            mask_text(debug),
+            r#"CommandRequest { command: "status", args: "", password: "***" }"#
+        );
+        let text = r#"{"cmd":"status","secret":"abc"}"#;
+        assert_eq!(mask_text(text), r#"{"cmd":"status","secret":"***"}"#);
+    }
+
+    #[test]
+    fn rotation_keeps_max_files() {
+        let dir = std::env::temp_dir().join(format!("rustit-audit-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("audit.log").to_string_lossy().to_string();
+        let profile = Profile {
+            file: file.clone(),
+            max_files: 2,
+            ..Profile::new()
+        };
+        for generation in ["a", "b", "c"] {
+            fs::write(&file, generation).unwrap();
+            rotate(&profile);
+        }
+        assert!(!std::path::Path::new(&file).exists());
+        assert_eq!(fs::read_to_string(format!("{}.1", file)).unwrap(), "c");
+        assert_eq!(fs::read_to_string(format!("{}.2", file)).unwrap(), "b");
+        assert!(!std::path::Path::new(&format!("{}.3", file)).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,541 @@
+use switch_sys::*;
+use serde::Deserialize;
+use utoipa::ToSchema;
+use std::collections::HashMap;
+
+/// Why a call control request failed.
+#[derive(Debug, Clone)]
+pub enum Error {
+    /// The request itself is wrong.
+    Invalid(String),
+    /// The channel doesn't exist.
+    NotFound(String),
+    /// The command policy of the client denies it.
+    Denied(String),
+    /// FreeSWITCH refused or failed the command.
+    Failed(String),
+}
+
+impl Error {
+    pub fn code(&self) -> i32 {
+        match self {
+            Error::Invalid(_) => 400,
+            Error::Denied(_) => 403,
+            Error::NotFound(_) => 404,
+            Error::Failed(_) => 500,
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Invalid(e) | Error::Denied(e) | Error::NotFound(e) | Error::Failed(e) => {
+                write!(f, "{}", e)
+            }
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Who a request is for, the commands it runs are checked against the policy.
+#[derive(Debug, Clone, Default)]
+pub struct Client {
+    pub name: String,
+    pub policy: String,
+}
+
+impl Client {
+    fn check(&self, command: &str, args: &str) -> Result<()> {
+        crate::policy::check(&self.policy, &self.name, command, args).map_err(Error::Denied)
+    }
+}
+
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct Originate {
+    /// Dial strings, called together unless `sequential`
+    pub endpoints: Vec<String>,
+    #[serde(default)]
+    pub sequential: bool,
+    /// Channel variables of the new call
+    #[serde(default)]
+    pub variables: HashMap<String, String>,
+    /// Application to run on answer, or the `extension` to route to
+    #[serde(default)]
+    pub app: String,
+    #[serde(default)]
+    pub app_args: String,
+    #[serde(default)]
+    pub extension: String,
+    #[serde(default)]
+    pub dialplan: String,
+    #[serde(default)]
+    pub context: String,
+    #[serde(default)]
+    pub caller_id_name: String,
+    #[serde(default)]
+    pub caller_id_number: String,
+    /// Seconds to wait for answer, 0 uses the default
+    #[serde(default)]
+    pub timeout: u32,
+}
+
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct Channel {
+    pub uuid: String,
+}
+
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct Hangup {
+    pub uuid: String,
+    /// Hangup cause, e.g. `NORMAL_CLEARING` or a Q.850 code
+    #[serde(default)]
+    pub cause: String,
+}
+
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct Transfer {
+    pub uuid: String,
+    pub destination: String,
+    #[serde(default)]
+    pub dialplan: String,
+    #[serde(default)]
+    pub context: String,
+    /// `aleg` (default), `bleg` or `both`
+    #[serde(default)]
+    pub leg: String,
+}
+
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct Bridge {
+    pub uuid: String,
+    pub other_uuid: String,
+}
+
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct Hold {
+    pub uuid: String,
+    /// `false` takes the call off hold
+    #[serde(default = "default_true")]
+    pub hold: bool,
+}
+
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct Play {
+    pub uuid: String,
+    pub path: String,
+    /// `aleg` (default), `bleg` or `both`
+    #[serde(default)]
+    pub leg: String,
+}
+
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct Execute {
+    pub uuid: String,
+    /// Dialplan application, e.g. `playback`
+    pub app: String,
+    #[serde(default)]
+    pub args: String,
+    /// Times to run, 0 runs once
+    #[serde(default)]
+    pub loops: u32,
+    /// Hold the channel lock, so executions queued together run in order
+    #[serde(default)]
+    pub event_lock: bool,
+}
+
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct Record {
+    pub uuid: String,
+    pub path: String,
+    /// `false` stops the recording
+    #[serde(default = "default_true")]
+    pub start: bool,
+    /// Maximum seconds, 0 is unlimited
+    #[serde(default)]
+    pub limit: u32,
+}
+
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct Dtmf {
+    pub uuid: String,
+    pub digits: String,
+    /// Milliseconds per digit, 0 uses the default
+    #[serde(default)]
+    pub duration: u32,
+}
+
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct SetVariable {
+    pub uuid: String,
+    pub name: String,
+    #[serde(default)]
+    pub value: String,
+}
+
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct GetVariable {
+    pub uuid: String,
+    pub name: String,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn check_text(field: &str, value: &str) -> Result<()> {
+    if value.trim().is_empty() {
+        return Err(Error::Invalid(format!("-ERR Missing {}", field)));
+    }
+    if value.contains(|c: char| c.is_control()) {
+        return Err(Error::Invalid(format!("-ERR Invalid {}", field)));
+    }
+    Ok(())
+}
+
+/// Values that are passed as one argument
+fn check_word(field: &str, value: &str) -> Result<()> {
+    check_text(field, value)?;
+    if value.contains(char::is_whitespace) {
+        return Err(Error::Invalid(format!("-ERR Invalid {} [{}]", field, value)));
+    }
+    Ok(())
+}
+
+fn check_name(field: &str, value: &str) -> Result<()> {
+    check_text(field, value)?;
+    if !value
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.')
+    {
+        return Err(Error::Invalid(format!("-ERR Invalid {} [{}]", field, value)));
+    }
+    Ok(())
+}
+
+/// A file to play, `app::args` would make uuid_broadcast run an application.
+fn check_path(path: &str) -> Result<()> {
+    check_word("path", path)?;
+    if path.contains("::") {
+        return Err(Error::Invalid(format!("-ERR Invalid path [{}]", path)));
+    }
+    Ok(())
+}
+
+fn check_uuid(uuid: &str) -> Result<()> {
+    check_word("uuid", uuid)?;
+    if !switch_sys::uuid_exists(uuid) {
+        return Err(Error::NotFound(format!("-ERR No such channel [{}]", uuid)));
+    }
+    Ok(())
+}
+
+fn check_leg(leg: &str) -> Result<()> {
+    match leg {
+        "" | "aleg" | "bleg" | "both" => Ok(()),
+        _ => Err(Error::Invalid(format!("-ERR Invalid leg [{}]", leg))),
+    }
+}
+
+/// Run an api command the policy of `client` allows.
+fn exec(client: &Client, cmd: &str, args: &str) -> Result<String> {
+    client.check(cmd, args)?;
+    run(cmd, args)
+}
+
+/// Run an api command, replies starting with `-ERR` or `-USAGE` are failures.
+fn run(cmd: &str, args: &str) -> Result<String> {
+    debug!("call control: {} {}", cmd, args);
+    let reply = switch_sys::api_exec(cmd, args).map_err(Error::Failed)?;
+    let reply = reply.trim().to_string();
+    if reply.starts_with("-ERR") || reply.starts_with("-USAGE") {
+        return Err(Error::Failed(reply));
+    }
+    Ok(reply)
+}
+
+fn reply(uuid: &str, reply: String) -> serde_json::Value {
+    serde_json::json!({ "uuid": uuid, "reply": reply })
+}
+
+/// Quote a channel variable value for a `{name=value}` prefix.
+fn quote_variable(value: &str) -> Result<String> {
+    if value.contains(|c: char| c == '\'' || c == '}' || c.is_control()) {
+        return Err(Error::Invalid(format!("-ERR Invalid variable value [{}]", value)));
+    }
+    let value = value.replace(',', "\\,");
+    if value.contains(char::is_whitespace) {
+        Ok(format!("'{}'", value))
+    } else {
+        Ok(value)
+    }
+}
+
+/// Place a new call, returns the uuid of the a-leg.
+pub fn originate(client: &Client, request: &Originate) -> Result<serde_json::Value> {
+    if request.endpoints.is_empty() {
+        return Err(Error::Invalid(String::from("-ERR Missing endpoints")));
+    }
+    for endpoint in &request.endpoints {
+        check_word("endpoint", endpoint)?;
+    }
+    if request.app.is_empty() == request.extension.is_empty() {
+        return Err(Error::Invalid(String::from(
+            "-ERR Either app or extension is required",
+        )));
+    }
+
+    let mut variables = request.variables.clone();
+    let uuid = match variables.get("origination_uuid") {
+        Some(uuid) => uuid.clone(),
+        None => {
+            let uuid = run("create_uuid", "")?;
+            variables.insert(String::from("origination_uuid"), uuid.clone());
+            uuid
+        }
+    };
+    if request.timeout > 0 {
+        variables.insert(
+            String::from("originate_timeout"),
+            request.timeout.to_string(),
+        );
+    }
+    let mut vars = Vec::new();
+    for (name, value) in &variables {
+        check_name("variable name", name)?;
+        vars.push(format!("{}={}", name, quote_variable(value)?));
+    }
+
+    let separator = if request.sequential { "|" } else { "," };
+    let mut args = format!("{{{}}}{}", vars.join(","), request.endpoints.join(separator));
+    if !request.app.is_empty() {
+        check_name("app", &request.app)?;
+        if request.app_args.contains(|c: char| c == ')' || c.is_control()) {
+            return Err(Error::Invalid(String::from("-ERR Invalid app_args")));
+        }
+        client.check(&request.app, &request.app_args)?;
+        args.push_str(&format!(" &{}({})", request.app, request.app_args));
+    } else {
+        check_word("extension", &request.extension)?;
+        args.push_str(&format!(" {}", request.extension));
+        let optional = [
+            ("dialplan", &request.dialplan, "XML"),
+            ("context", &request.context, "default"),
+            ("caller_id_name", &request.caller_id_name, "undef"),
+            ("caller_id_number", &request.caller_id_number, "undef"),
+        ];
+        // Positional arguments, only as many as needed
+        let used = optional
+            .iter()
+            .rposition(|(_, value, _)| !value.is_empty())
+            .map_or(0, |i| i + 1);
+        for (field, value, default) in &optional[..used] {
+            if value.is_empty() {
+                args.push_str(&format!(" {}", default));
+            } else {
+                check_word(field, value)?;
+                args.push_str(&format!(" {}", value));
+            }
+        }
+    }
+
+    let reply = exec(client, "originate", &args)?;
+    Ok(serde_json::json!({ "uuid": uuid, "reply": reply }))
+}
+
+pub fn answer(client: &Client, request: &Channel) -> Result<serde_json::Value> {
+    check_uuid(&request.uuid)?;
+    let ret = exec(client, "uuid_answer", &request.uuid)?;
+    Ok(reply(&request.uuid, ret))
+}
+
+pub fn hangup(client: &Client, request: &Hangup) -> Result<serde_json::Value> {
+    check_uuid(&request.uuid)?;
+    let mut args = request.uuid.clone();
+    if !request.cause.is_empty() {
+        let cause = request.cause.to_ascii_uppercase();
+        if !cause.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(Error::Invalid(format!("-ERR Invalid cause [{}]", request.cause)));
+        }
+        args.push(' ');
+        args.push_str(&cause);
+    }
+    let ret = exec(client, "uuid_kill", &args)?;
+    Ok(reply(&request.uuid, ret))
+}
+
+pub fn transfer(client: &Client, request: &Transfer) -> Result<serde_json::Value> {
+    check_uuid(&request.uuid)?;
+    check_word("destination", &request.destination)?;
+    check_leg(&request.leg)?;
+    let mut args = request.uuid.clone();
+    match request.leg.as_str() {
+        "bleg" => args.push_str(" -bleg"),
+        "both" => args.push_str(" -both"),
+        _ => {}
+    }
+    args.push_str(&format!(" {}", request.destination));
+    if !request.dialplan.is_empty() || !request.context.is_empty() {
+        let dialplan = if request.dialplan.is_empty() { "XML" } else { &request.dialplan };
+        check_word("dialplan", dialplan)?;
+        args.push_str(&format!(" {}", dialplan));
+    }
+    if !request.context.is_empty() {
+        check_word("context", &request.context)?;
+        args.push_str(&format!(" {}", request.context));
+    }
+    let ret = exec(client, "uuid_transfer", &args)?;
+    Ok(reply(&request.uuid, ret))
+}
+
+pub fn bridge(client: &Client, request: &Bridge) -> Result<serde_json::Value> {
+    check_uuid(&request.uuid)?;
+    check_uuid(&request.other_uuid)?;
+    let ret = exec(
+        client,
+        "uuid_bridge",
+        &format!("{} {}", request.uuid, request.other_uuid),
+    )?;
+    Ok(reply(&request.uuid, ret))
+}
+
+pub fn park(client: &Client, request: &Channel) -> Result<serde_json::Value> {
+    check_uuid(&request.uuid)?;
+    let ret = exec(client, "uuid_park", &request.uuid)?;
+    Ok(reply(&request.uuid, ret))
+}
+
+pub fn hold(client: &Client, request: &Hold) -> Result<serde_json::Value> {
+    check_uuid(&request.uuid)?;
+    let args = if request.hold {
+        request.uuid.clone()
+    } else {
+        format!("off {}", request.uuid)
+    };
+    let ret = exec(client, "uuid_hold", &args)?;
+    Ok(reply(&request.uuid, ret))
+}
+
+pub fn play(client: &Client, request: &Play) -> Result<serde_json::Value> {
+    check_path(&request.path)?;
+    check_leg(&request.leg)?;
+    check_uuid(&request.uuid)?;
+    let leg = if request.leg.is_empty() { "aleg" } else { &request.leg };
+    let ret = exec(
+        client,
+        "uuid_broadcast",
+        &format!("{} {} {}", request.uuid, request.path, leg),
+    )?;
+    Ok(reply(&request.uuid, ret))
+}
+
+pub fn execute(client: &Client, request: &Execute) -> Result<serde_json::Value> {
+    check_uuid(&request.uuid)?;
+    check_name("app", &request.app)?;
+    if request.args.contains(|c: char| c.is_control()) {
+        return Err(Error::Invalid(String::from("-ERR Invalid args")));
+    }
+    client.check(&request.app, &request.args)?;
+    let mut headers = HashMap::new();
+    headers.insert(String::from("call-command"), String::from("execute"));
+    headers.insert(String::from("execute-app-name"), request.app.clone());
+    if !request.args.is_empty() {
+        headers.insert(String::from("execute-app-arg"), request.args.clone());
+    }
+    if request.loops > 1 {
+        headers.insert(String::from("loops"), request.loops.to_string());
+    }
+    if request.event_lock {
+        headers.insert(String::from("event-lock"), String::from("true"));
+    }
+    debug!("call control: execute {} {} {}", request.uuid, request.app, request.args);
+    let ret = switch_sys::sendmsg(&request.uuid, headers).map_err(Error::Failed)?;
+    Ok(reply(&request.uuid, ret))
+}
+
+pub fn record(client: &Client, request: &Record) -> Result<serde_json::Value> {
+    check_uuid(&request.uuid)?;
+    check_word("path", &request.path)?;
+    let mut args = if request.start {
+        format!("{} start {}", request.uuid, request.path)
+    } else {
+        format!("{} stop {}", request.uuid, request.path)
+    };
+    if request.start && request.limit > 0 {
+        args.push_str(&format!(" {}", request.limit));
+    }
+    let ret = exec(client, "uuid_record", &args)?;
+    Ok(reply(&request.uuid, ret))
+}
+
+pub fn dtmf(client: &Client, request: &Dtmf) -> Result<serde_json::Value> {
+    check_uuid(&request.uuid)?;
+    check_text("digits", &request.digits)?;
+    if !request
+        .digits
+        .chars()
+        .all(|c| c.is_ascii_digit() || "*#ABCDabcdwW".contains(c))
+    {
+        return Err(Error::Invalid(format!("-ERR Invalid digits [{}]", request.digits)));
+    }
+    let mut args = format!("{} {}", request.uuid, request.digits);
+    if request.duration > 0 {
+        args.push_str(&format!("@{}", request.duration));
+    }
+    let ret = exec(client, "uuid_send_dtmf", &args)?;
+    Ok(reply(&request.uuid, ret))
+}
+
+pub fn set_variable(client: &Client, request: &SetVariable) -> Result<serde_json::Value> {
+    check_uuid(&request.uuid)?;
+    check_name("name", &request.name)?;
+    if request.value.contains(|c: char| c.is_control()) {
+        return Err(Error::Invalid(String::from("-ERR Invalid value")));
+    }
+    let mut args = format!("{} {}", request.uuid, request.name);
+    if !request.value.is_empty() {
+        args.push_str(&format!(" {}", request.value));
+    }
+    let ret = exec(client, "uuid_setvar", &args)?;
+    Ok(reply(&request.uuid, ret))
+}
+
+pub fn get_variable(client: &Client, request: &GetVariable) -> Result<serde_json::Value> {
+    check_uuid(&request.uuid)?;
+    check_name("name", &request.name)?;
+    client.check("uuid_getvar", &format!("{} {}", request.uuid, request.name))?;
+    let value = switch_sys::get_channel_variable(&request.uuid, &request.name)
+        .map_err(Error::NotFound)?;
+    Ok(serde_json::json!({
+        "uuid": request.uuid,
+        "name": request.name,
+        "value": value,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn play_path_is_one_file() {
+        assert!(check_path("/tmp/hello.wav").is_ok());
+        assert!(check_path("hfs://prompts/hello.wav").is_ok());
+        assert!(check_path("tone_stream://%(100,100,350,440)").is_ok());
+        assert!(matches!(check_path("/tmp/a.wav both"), Err(Error::Invalid(_))));
+        assert!(matches!(check_path(""), Err(Error::Invalid(_))));
+    }
+
+    #[test]
+    fn play_path_cannot_run_an_app() {
+        assert!(matches!(check_path("system::id"), Err(Error::Invalid(_))));
+        assert!(matches!(check_path("/tmp/a.wav::x"), Err(Error::Invalid(_))));
+        let request = Play {
+            uuid: String::from("not-checked"),
+            path: String::from("system::touch /tmp/x"),
+            leg: String::new(),
+        };
+        assert!(matches!(play(&Client::default(), &request), Err(Error::Invalid(_))));
+    }
+}
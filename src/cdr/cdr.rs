@@ -200,6 +200,12 @@ pub fn process_cdr(profile: super::Profile, cdr_data: CdrData) {
         }
     }
 
+    if success {
+        crate::metrics::CDR_POSTS.inc();
+    } else {
+        crate::metrics::CDR_FAILURES.inc();
+    }
+
     if !success {
         error!("Unable to post cdr to web server [{}]", &cdr_data.uuid);
         if profile.log_errors_to_disk {
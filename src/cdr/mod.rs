@@ -68,7 +68,9 @@ unsafe extern "C" fn on_reporting(session: *mut switch_core_session_t) -> switch
             let cdr = cdr::generate_cdr(&profile, session);
             match cdr {
                 Ok(cdr) => {
+                    crate::metrics::CDR_QUEUE.inc();
                     cdr::process_cdr(profile, cdr);
+                    crate::metrics::CDR_QUEUE.dec();
                     switch_status_t::SWITCH_STATUS_SUCCESS
                 }
                 Err(status) => status,
@@ -0,0 +1,245 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// A header condition of a subscription topic as sent by clients,
+/// `op` is one of `equals` (default), `prefix` or `regex`.
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct HeaderFilter {
+    pub header: String,
+    #[serde(default)]
+    pub op: String,
+    pub value: String,
+}
+
+#[derive(Debug, Clone)]
+enum Matcher {
+    Equals(String),
+    Prefix(String),
+    Regex(regex::Regex),
+}
+
+/// A compiled header condition.
+#[derive(Debug, Clone)]
+pub struct Predicate {
+    header: String,
+    matcher: Matcher,
+}
+
+impl Predicate {
+    pub fn new(header: &str, op: &str, value: &str) -> Result<Predicate, String> {
+        if header.is_empty() {
+            return Err(String::from("Filter header is empty"));
+        }
+        let matcher = if op.is_empty() || op.eq_ignore_ascii_case("equals") {
+            Matcher::Equals(value.to_string())
+        } else if op.eq_ignore_ascii_case("prefix") {
+            Matcher::Prefix(value.to_string())
+        } else if op.eq_ignore_ascii_case("regex") {
+            let re = regex::Regex::new(value)
+                .map_err(|e| format!("Invalid filter regex [{}], {}", value, e))?;
+            Matcher::Regex(re)
+        } else {
+            return Err(format!("Unknown filter op [{}]", op));
+        };
+        Ok(Predicate {
+            header: header.to_string(),
+            matcher,
+        })
+    }
+
+    pub fn from(filter: &HeaderFilter) -> Result<Predicate, String> {
+        Predicate::new(&filter.header, &filter.op, &filter.value)
+    }
+
+    /// A missing header never matches.
+    pub fn matches(&self, headers: &HashMap<String, String>) -> bool {
+        let value = headers.get(&self.header).or_else(|| {
+            headers
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(&self.header))
+                .map(|(_, value)| value)
+        });
+        match value {
+            Some(value) => match &self.matcher {
+                Matcher::Equals(expected) => value == expected,
+                Matcher::Prefix(prefix) => value.starts_with(prefix.as_str()),
+                Matcher::Regex(re) => re.is_match(value),
+            },
+            None => false,
+        }
+    }
+}
+
+pub fn compile(filters: &[HeaderFilter]) -> Result<Vec<Predicate>, String> {
+    filters.iter().map(Predicate::from).collect()
+}
+
+pub fn matches_all(predicates: &[Predicate], headers: &HashMap<String, String>) -> bool {
+    predicates.iter().all(|predicate| predicate.matches(headers))
+}
+
+/// Keep only the named headers, an empty list keeps everything.
+pub fn project(headers: &mut HashMap<String, String>, names: &[String]) {
+    if names.is_empty() {
+        return;
+    }
+    headers.retain(|name, _| names.iter().any(|n| n.eq_ignore_ascii_case(name)));
+}
+
+/// Headers that point at the other legs of a call.
+const RELATED_HEADERS: [&str; 3] = [
+    "Other-Leg-Unique-ID",
+    "Bridge-A-Unique-ID",
+    "Bridge-B-Unique-ID",
+];
+
+/// Follows the channels of the calls a subscriber asked for,
+/// legs bridged to a tracked channel are tracked too.
+#[derive(Debug, Default)]
+pub struct CallTracker {
+    legs: HashSet<String>,
+    hungup: HashSet<String>,
+}
+
+impl CallTracker {
+    /// Channels that don't exist are dropped, `None` if nothing is left.
+    pub fn new(uuids: &[String]) -> Option<CallTracker> {
+        let legs: HashSet<String> = uuids
+            .iter()
+            .filter(|uuid| switch_sys::uuid_exists(uuid))
+            .cloned()
+            .collect();
+        if legs.is_empty() {
+            return None;
+        }
+        Some(CallTracker {
+            legs,
+            hungup: HashSet::new(),
+        })
+    }
+
+    /// Whether the event belongs to a tracked call, learning new legs on the way.
+    pub fn track(&mut self, event_id: u32, headers: &HashMap<String, String>) -> bool {
+        let uuid = headers.get("Unique-ID");
+        let related: Vec<&String> = RELATED_HEADERS
+            .iter()
+            .filter_map(|name| headers.get(*name))
+            .filter(|value| !value.is_empty())
+            .collect();
+        let belongs = uuid.is_some_and(|uuid| self.legs.contains(uuid))
+            || related.iter().any(|leg| self.legs.contains(*leg));
+        if !belongs {
+            return false;
+        }
+
+        if let Some(uuid) = uuid {
+            self.legs.insert(uuid.clone());
+            if event_id == switch_sys::switch_event_types_t::SWITCH_EVENT_CHANNEL_DESTROY.0 {
+                self.hungup.insert(uuid.clone());
+            }
+        }
+        for leg in related {
+            if !self.legs.contains(leg) && switch_sys::uuid_exists(leg) {
+                self.legs.insert(leg.clone());
+            }
+        }
+        true
+    }
+
+    /// All tracked channels are gone.
+    pub fn done(&self) -> bool {
+        self.legs.iter().all(|leg| self.hungup.contains(leg))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn operators_match_header_values() {
+        let h = headers(&[("Caller-Destination-Number", "1000"), ("variable_domain", "a.example.com")]);
+
+        let equals = Predicate::new("Caller-Destination-Number", "", "1000").unwrap();
+        assert!(equals.matches(&h));
+        let equals = Predicate::new("Caller-Destination-Number", "EQUALS", "100").unwrap();
+        assert!(!equals.matches(&h));
+
+        let prefix = Predicate::new("Caller-Destination-Number", "prefix", "10").unwrap();
+        assert!(prefix.matches(&h));
+        let prefix = Predicate::new("Caller-Destination-Number", "prefix", "2").unwrap();
+        assert!(!prefix.matches(&h));
+
+        let regex = Predicate::new("variable_domain", "regex", r"\.example\.com$").unwrap();
+        assert!(regex.matches(&h));
+        let regex = Predicate::new("variable_domain", "regex", r"^b\.").unwrap();
+        assert!(!regex.matches(&h));
+    }
+
+    #[test]
+    fn header_names_are_case_insensitive_and_missing_never_match() {
+        let h = headers(&[("Unique-ID", "abc")]);
+        assert!(Predicate::new("unique-id", "", "abc").unwrap().matches(&h));
+        assert!(!Predicate::new("Other-Leg-Unique-ID", "prefix", "").unwrap().matches(&h));
+    }
+
+    #[test]
+    fn invalid_filters_are_refused() {
+        assert!(Predicate::new("", "", "x").is_err());
+        assert!(Predicate::new("Unique-ID", "contains", "x").is_err());
+        assert!(Predicate::new("Unique-ID", "regex", "(").is_err());
+        let filters = vec![HeaderFilter {
+            header: String::from("Unique-ID"),
+            op: String::from("glob"),
+            value: String::from("*"),
+        }];
+        assert!(compile(&filters).is_err());
+    }
+
+    #[test]
+    fn all_predicates_must_match() {
+        let h = headers(&[("A", "1"), ("B", "2")]);
+        let both = vec![
+            Predicate::new("A", "", "1").unwrap(),
+            Predicate::new("B", "", "2").unwrap(),
+        ];
+        assert!(matches_all(&both, &h));
+        let one = vec![
+            Predicate::new("A", "", "1").unwrap(),
+            Predicate::new("B", "", "3").unwrap(),
+        ];
+        assert!(!matches_all(&one, &h));
+        assert!(matches_all(&[], &h));
+    }
+
+    #[test]
+    fn projection_keeps_named_headers() {
+        let mut h = headers(&[("Event-Name", "HEARTBEAT"), ("Up-Time", "1"), ("Core-UUID", "x")]);
+        project(&mut h, &[]);
+        assert_eq!(h.len(), 3);
+        project(&mut h, &[String::from("event-name"), String::from("Core-UUID"), String::from("Missing")]);
+        assert_eq!(h, headers(&[("Event-Name", "HEARTBEAT"), ("Core-UUID", "x")]));
+    }
+
+    #[test]
+    fn tracker_is_done_when_its_channels_are_destroyed() {
+        let destroy = switch_sys::switch_event_types_t::SWITCH_EVENT_CHANNEL_DESTROY.0;
+        let answer = switch_sys::switch_event_types_t::SWITCH_EVENT_CHANNEL_ANSWER.0;
+        let mut tracker = CallTracker {
+            legs: ["a".to_string()].into(),
+            hungup: HashSet::new(),
+        };
+        assert!(!tracker.track(answer, &headers(&[("Unique-ID", "b")])));
+        assert!(tracker.track(answer, &headers(&[("Unique-ID", "a")])));
+        assert!(!tracker.done());
+        assert!(tracker.track(destroy, &headers(&[("Unique-ID", "a")])));
+        assert!(tracker.done());
+    }
+}
@@ -0,0 +1,136 @@
+use crate::audit::Pending;
+use super::zrapi::{self, base_server::Base};
+use tonic::{Code, Request, Response, Status};
+
+/// The HTTP status a gRPC error is recorded as.
+pub fn http_code(code: Code) -> i32 {
+    match code {
+        Code::Ok => 200,
+        Code::InvalidArgument | Code::OutOfRange | Code::FailedPrecondition => 400,
+        Code::Unauthenticated => 401,
+        Code::PermissionDenied => 403,
+        Code::NotFound => 404,
+        Code::AlreadyExists | Code::Aborted => 409,
+        Code::ResourceExhausted => 429,
+        Code::Unimplemented => 501,
+        Code::Unavailable => 503,
+        Code::DeadlineExceeded => 504,
+        _ => 500,
+    }
+}
+
+/// Records every call of the wrapped service in the audit log,
+/// refusing the methods the token of the caller doesn't grant.
+pub struct Audited<S>(pub S);
+
+fn begin<T: std::fmt::Debug>(
+    request: &Request<T>,
+    operation: &str,
+) -> Result<(Pending, String, String), Status> {
+    let caller = request.extensions().get::<super::Caller>();
+    let identity = caller.map(|caller| caller.name.clone()).unwrap_or_default();
+    let remote = request
+        .remote_addr()
+        .map(|remote_addr| remote_addr.ip().to_string())
+        .unwrap_or_default();
+    let args = serde_json::Value::String(format!("{:?}", request.get_ref()));
+    let pending = Pending::new("grpc", operation, args);
+    if caller.is_some_and(|caller| !caller.allows(operation)) {
+        pending.finish(&identity, &remote, 403);
+        return Err(Status::permission_denied(format!("{} is not allowed", operation)));
+    }
+    Ok((pending, identity, remote))
+}
+
+/// `async_trait` has to see the methods, so the whole impl is generated.
+macro_rules! audited {
+    ($($method:ident($request:ident) $operation:literal;)*) => {
+        #[tonic::async_trait]
+        impl<S: Base> Base for Audited<S> {
+            type SubscribeStream = S::SubscribeStream;
+
+            async fn subscribe(
+                &self,
+                request: Request<zrapi::SubscribeRequest>,
+            ) -> Result<Response<Self::SubscribeStream>, Status> {
+                let (pending, identity, remote) = begin(&request, "Subscribe")?;
+                let res = self.0.subscribe(request).await;
+                let code = match &res {
+                    Ok(_) => 200,
+                    Err(status) => http_code(status.code()),
+                };
+                pending.finish(&identity, &remote, code);
+                res
+            }
+
+            type SessionStream = S::SessionStream;
+
+            async fn session(
+                &self,
+                request: Request<tonic::Streaming<zrapi::SessionRequest>>,
+            ) -> Result<Response<Self::SessionStream>, Status> {
+                let (pending, identity, remote) = begin(&request, "Session")?;
+                let res = self.0.session(request).await;
+                let code = match &res {
+                    Ok(_) => 200,
+                    Err(status) => http_code(status.code()),
+                };
+                pending.finish(&identity, &remote, code);
+                res
+            }
+
+            $(
+                async fn $method(
+                    &self,
+                    request: Request<zrapi::$request>,
+                ) -> Result<Response<zrapi::Reply>, Status> {
+                    let (pending, identity, remote) = begin(&request, $operation)?;
+                    let res = self.0.$method(request).await;
+                    let code = match &res {
+                        Ok(reply) => reply.get_ref().code,
+                        Err(status) => http_code(status.code()),
+                    };
+                    pending.finish(&identity, &remote, code);
+                    res
+                }
+            )*
+        }
+    };
+}
+
+audited! {
+    command(CommandRequest) "Command";
+    send_msg(SendMsgRequest) "SendMsg";
+    send_event(SendEventRequest) "SendEvent";
+    reload_xml(ReloadXmlRequest) "ReloadXML";
+    reload_acl(ReloadAclRequest) "ReloadAcl";
+    reload_mod(ModRequest) "ReloadMod";
+    load_mod(ModRequest) "LoadMod";
+    unload_mod(ModRequest) "UnloadMod";
+    jsapi(JsapiRequest) "JSAPI";
+    storage_list(StorageListRequest) "StorageList";
+    storage_purge(StoragePurgeRequest) "StoragePurge";
+    storage_prefetch(StoragePrefetchRequest) "StoragePrefetch";
+    storage_resync(StorageResyncRequest) "StorageResync";
+    originate(OriginateRequest) "Originate";
+    answer(ChannelRequest) "Answer";
+    hangup(HangupRequest) "Hangup";
+    transfer(TransferRequest) "Transfer";
+    bridge(BridgeRequest) "Bridge";
+    park(ChannelRequest) "Park";
+    hold(HoldRequest) "Hold";
+    play(PlayRequest) "Play";
+    record(RecordRequest) "Record";
+    send_dtmf(DtmfRequest) "SendDtmf";
+    set_variable(SetVariableRequest) "SetVariable";
+    get_variable(GetVariableRequest) "GetVariable";
+    execute(ExecuteRequest) "Execute";
+    submit_job(JobRequest) "SubmitJob";
+    get_job(GetJobRequest) "GetJob";
+    system_status(SystemStatusRequest) "SystemStatus";
+    list_channels(ListRequest) "ListChannels";
+    list_calls(ListRequest) "ListCalls";
+    list_registrations(ListRequest) "ListRegistrations";
+    list_modules(ListRequest) "ListModules";
+    list_gateways(ListRequest) "ListGateways";
+}
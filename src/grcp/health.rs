@@ -0,0 +1,81 @@
+use switch_sys::*;
+use super::health_proto::health_check_response::ServingStatus;
+use super::health_proto::{health_server, HealthCheckRequest, HealthCheckResponse};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+
+/// Services the health of which can be asked, empty is the server as a whole.
+const SERVICES: [&str; 4] = [
+    "",
+    "zrapi.Base",
+    "grpc.health.v1.Health",
+    "grpc.reflection.v1alpha.ServerReflection",
+];
+
+/// How often `Watch` looks at the core readiness.
+const WATCH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Every service is serving while the FreeSWITCH core is ready.
+pub struct Health;
+
+async fn serving_status(service: &str) -> ServingStatus {
+    if !SERVICES.contains(&service) {
+        return ServingStatus::ServiceUnknown;
+    }
+    let handle = tokio::task::spawn_blocking(|| switch_sys::status().ready);
+    match handle.await {
+        Ok(true) => ServingStatus::Serving,
+        _ => ServingStatus::NotServing,
+    }
+}
+
+#[tonic::async_trait]
+impl health_server::Health for Health {
+    type WatchStream = ReceiverStream<Result<HealthCheckResponse, Status>>;
+
+    async fn check(
+        &self,
+        request: Request<HealthCheckRequest>,
+    ) -> Result<Response<HealthCheckResponse>, Status> {
+        let service = request.into_inner().service;
+        match serving_status(&service).await {
+            ServingStatus::ServiceUnknown => {
+                Err(Status::not_found(format!("Unknown service [{}]", service)))
+            }
+            status => Ok(Response::new(HealthCheckResponse {
+                status: status as i32,
+            })),
+        }
+    }
+
+    /// Send the status, then again every time it changes.
+    async fn watch(
+        &self,
+        request: Request<HealthCheckRequest>,
+    ) -> Result<Response<Self::WatchStream>, Status> {
+        let service = request.into_inner().service;
+        let (tx, rx) = mpsc::channel(1);
+        tokio::spawn(async move {
+            let mut last = None;
+            loop {
+                let status = serving_status(&service).await;
+                if last != Some(status) {
+                    debug!("GRPC health [{}] {}", service, status.as_str_name());
+                    let response = HealthCheckResponse {
+                        status: status as i32,
+                    };
+                    if tx.send(Ok(response)).await.is_err() {
+                        break;
+                    }
+                    last = Some(status);
+                }
+                tokio::select! {
+                    _ = tx.closed() => break,
+                    _ = tokio::time::sleep(WATCH_INTERVAL) => (),
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
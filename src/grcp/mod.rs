@@ -1,3 +1,6 @@
+// tonic::Status is large, but it is what interceptors and services return.
+#![allow(clippy::result_large_err)]
+
 use lazy_static::lazy_static;
 use md5;
 use std::ffi::CString;
@@ -5,12 +8,51 @@ use std::sync::Arc;
 use std::sync::Mutex;
 use std::thread;
 use switch_sys::*;
-use tokio::sync::broadcast;
+use crate::limit::Limits;
+use crate::replay::Replay;
 use tokio::sync::mpsc;
 use tonic::{Request, Status};
 
+pub mod audit;
+pub mod health;
+pub mod reflection;
 pub mod service;
 pub mod zrapi;
+#[path = "grpc.health.v1.rs"]
+pub mod health_proto;
+#[path = "grpc.reflection.v1alpha.rs"]
+pub mod reflection_proto;
+
+/// Who a request was authenticated as, for the command policy.
+#[derive(Debug, Clone)]
+pub struct Caller {
+    pub name: String,
+    pub policy: String,
+    /// Methods it may call, `*` is all and none are granted when empty
+    pub rpcs: Vec<String>,
+}
+
+impl Caller {
+    /// Whether the caller may call the method `rpc`, e.g. `Command`.
+    pub fn allows(&self, rpc: &str) -> bool {
+        self.rpcs
+            .iter()
+            .any(|allowed| allowed == "*" || allowed.eq_ignore_ascii_case(rpc))
+    }
+}
+
+/// A client certificate common name allowed to call, `<client name="cn"/>`.
+#[derive(Debug, Clone)]
+struct Client {
+    pub name: String,
+    /// Command policy, empty uses the profile one
+    pub policy: String,
+    /// Methods it may call, `*` is all
+    pub rpcs: Vec<String>,
+}
+
+/// Audience of the tokens accepted by the gRPC server.
+const TOKEN_AUDIENCE: &str = "grpc";
 
 #[derive(Debug, Clone)]
 struct Profile {
@@ -19,6 +61,28 @@ struct Profile {
     pub listen_port: u16,
     pub password: String,
     pub apply_inbound_acl: String,
+    pub tls_cert: String,
+    pub tls_key: String,
+    pub tls_client_ca: String,
+    pub event_replay_size: usize,
+    /// Requests per second per client, 0 is unlimited
+    pub rate_limit: f64,
+    pub rate_burst: u32,
+    /// Concurrent blocking executions, 0 is unlimited
+    pub max_concurrent: usize,
+    /// Command policy of every client
+    pub command_policy: String,
+    /// Accept the md5 of the password as bearer,
+    /// unset is on unless a token key is configured
+    pub password_auth: Option<bool>,
+    /// Secret of HS256 tokens, minted by `rustit grpc token`
+    pub token_secret: String,
+    /// PEM public key of RS256 tokens minted elsewhere
+    pub token_public_key: String,
+    /// Default lifetime of minted tokens in seconds
+    pub token_ttl: u64,
+    /// Who client certificates may authenticate as
+    pub clients: Vec<Client>,
     pub enable: bool,
 }
 
@@ -30,6 +94,19 @@ impl Profile {
             listen_port: 8203,
             password: "".to_string(),
             apply_inbound_acl: "".to_string(),
+            tls_cert: "".to_string(),
+            tls_key: "".to_string(),
+            tls_client_ca: "".to_string(),
+            event_replay_size: 1024,
+            rate_limit: 0.0,
+            rate_burst: 0,
+            max_concurrent: 0,
+            command_policy: "".to_string(),
+            password_auth: None,
+            token_secret: "".to_string(),
+            token_public_key: "".to_string(),
+            token_ttl: 3600,
+            clients: Vec::new(),
             enable: false,
         }
     }
@@ -38,7 +115,7 @@ impl Profile {
 struct Global {
     running: Mutex<bool>,
     profile: Mutex<Profile>,
-    ev_tx: Mutex<Option<broadcast::Sender<zrapi::Event>>>,
+    events: Mutex<Option<Arc<Replay<zrapi::Event>>>>,
     done_tx: Mutex<Option<mpsc::Sender<u8>>>,
 }
 
@@ -47,7 +124,7 @@ impl Global {
         Global {
             profile: Mutex::new(Profile::new()),
             running: Mutex::new(false),
-            ev_tx: Mutex::new(None),
+            events: Mutex::new(None),
             done_tx: Mutex::new(None),
         }
     }
@@ -57,65 +134,182 @@ lazy_static! {
     static ref GOLOBAS: Arc<Global> = Arc::new(Global::new());
 }
 
+/// Build the TLS config of the gRPC server, client certificates are optional.
+fn tls_config(profile: &Profile) -> Result<tonic::transport::ServerTlsConfig, String> {
+    let cert = std::fs::read(&profile.tls_cert)
+        .map_err(|e| format!("open {}: {}", profile.tls_cert, e))?;
+    let key = std::fs::read(&profile.tls_key)
+        .map_err(|e| format!("open {}: {}", profile.tls_key, e))?;
+    let mut config = tonic::transport::ServerTlsConfig::new()
+        .identity(tonic::transport::Identity::from_pem(cert, key));
+    if !profile.tls_client_ca.is_empty() {
+        let ca = std::fs::read(&profile.tls_client_ca)
+            .map_err(|e| format!("open {}: {}", profile.tls_client_ca, e))?;
+        config = config
+            .client_ca_root(tonic::transport::Certificate::from_pem(ca))
+            .client_auth_optional(true);
+    }
+    Ok(config)
+}
+
+/// The credentials of a `bearer` authorization.
+fn bearer(authorization: &str) -> Option<&str> {
+    let (scheme, token) = authorization.split_once(' ')?;
+    scheme.eq_ignore_ascii_case("bearer").then(|| token.trim())
+}
+
+/// The verifier of bearer tokens, `None` when no key is configured.
+fn token_verifier(profile: &Profile) -> Result<Option<crate::token::Verifier>, String> {
+    if !profile.token_public_key.is_empty() {
+        let pem = std::fs::read(&profile.token_public_key)
+            .map_err(|e| format!("open {}: {}", profile.token_public_key, e))?;
+        return crate::token::Verifier::rsa_pem(&pem).map(Some);
+    }
+    if !profile.token_secret.is_empty() {
+        return Ok(Some(crate::token::Verifier::hmac(&profile.token_secret)));
+    }
+    Ok(None)
+}
+
 #[tokio::main]
-async fn tokio_main(addr: String, password: String, acl: String) {
+async fn tokio_main(addr: String, profile: Profile) {
+    let password = profile.password.clone();
+    let acl = profile.apply_inbound_acl.clone();
     let addr = addr.clone();
     let addr = addr
         .parse::<std::net::SocketAddr>()
         .expect("Unable to parse grpc socket address");
 
-    let (tx, mut rx) = broadcast::channel::<zrapi::Event>(64);
+    let events = Replay::new(profile.event_replay_size);
     let (done_tx, mut done_rx) = mpsc::channel(1);
 
     *GOLOBAS.done_tx.lock().unwrap() = Some(done_tx);
-    *GOLOBAS.ev_tx.lock().unwrap() = Some(tx.clone());
+    *GOLOBAS.events.lock().unwrap() = Some(events.clone());
 
     let f = async {
         let _ = done_rx.recv().await;
     };
 
-    let check_auth = move |req: Request<()>| -> Result<Request<()>, Status> {
-        let remote_addr = req.remote_addr();
-        if let Some(remote_addr) = remote_addr {
-            let remote_addr_str = remote_addr.ip().to_string();
-            if switch_sys::check_acl(&remote_addr_str, &acl) {
-                return Ok(req);
-            }
+    let command_policy = profile.command_policy.clone();
+    let clients = profile.clients.clone();
+    let token_key = !profile.token_secret.is_empty() || !profile.token_public_key.is_empty();
+    let password_auth = profile.password_auth.unwrap_or(!token_key);
+    if password_auth && token_key {
+        warn!("GRPC password-auth is on, the never expiring password is accepted next to tokens");
+    }
+    let verifier = match token_verifier(&profile) {
+        Ok(verifier) => verifier,
+        Err(e) => {
+            error!("Couldn't load grpc token key, {}", e);
+            return;
         }
+    };
+    let limits = Limits::new(profile.rate_limit, profile.rate_burst, profile.max_concurrent);
+    let auth_limits = limits.clone();
+    let authenticate = move |mut req: Request<()>| -> Result<Request<()>, Status> {
+        let remote_addr_str = req
+            .remote_addr()
+            .map(|remote_addr| remote_addr.ip().to_string())
+            .unwrap_or_default();
+        // A client certificate verified against tls-client-ca
+        let common_name = req
+            .peer_certs()
+            .and_then(|certs| certs.first().and_then(|cert| crate::tls::common_name(cert.get_ref())));
 
-        let authorization = req.metadata().get("authorization");
-        match authorization {
-            Some(t) => {
-                let digest = format!("bearer {:x}", md5::compute(&password));
-                let token = t.to_str().unwrap();
-                if digest.eq_ignore_ascii_case(token) {
-                    Ok(req)
-                } else {
-                    Err(Status::unauthenticated(
-                        "authentication failure wrong password",
-                    ))
+        let mut policy = command_policy.clone();
+        let mut rpcs = vec![String::from("*")];
+        let name = if !remote_addr_str.is_empty() && switch_sys::check_acl(&remote_addr_str, &acl) {
+            String::from("acl")
+        } else if let Some(common_name) = common_name {
+            debug!("GRPC client certificate [{}]", common_name);
+            let client = clients
+                .iter()
+                .find(|client| client.name == common_name)
+                .ok_or_else(|| Status::unauthenticated("authentication failure unknown client certificate"))?;
+            req.extensions_mut()
+                .insert(crate::tls::PeerCommonName(Some(common_name.clone())));
+            if !client.policy.is_empty() {
+                policy = client.policy.clone();
+            }
+            rpcs = client.rpcs.clone();
+            common_name
+        } else {
+            let authorization = req.metadata().get("authorization");
+            let token = authorization.and_then(|t| t.to_str().ok()).and_then(bearer);
+            match (token, &verifier) {
+                // A signed token, the md5 digest never has dots
+                (Some(token), Some(verifier)) if token.contains('.') => {
+                    let claims = verifier.verify(token, TOKEN_AUDIENCE).map_err(|e| {
+                        debug!("GRPC token {}", e);
+                        Status::unauthenticated("authentication failure invalid token")
+                    })?;
+                    // The claim replaces command-policy, but only with a configured one
+                    if !claims.policy.is_empty() {
+                        if !crate::policy::exists(&claims.policy) {
+                            debug!("GRPC token of [{}] has unknown policy [{}]", claims.sub, claims.policy);
+                            return Err(Status::unauthenticated("authentication failure unknown token policy"));
+                        }
+                        policy = claims.policy;
+                    }
+                    rpcs = claims.rpcs;
+                    claims.sub
                 }
+                (Some(token), _) if password_auth => {
+                    let digest = format!("{:x}", md5::compute(&password));
+                    if !crate::token::secret_eq(&token.to_ascii_lowercase(), &digest) {
+                        return Err(Status::unauthenticated(
+                            "authentication failure wrong password",
+                        ));
+                    }
+                    String::from("password")
+                }
+                _ => return Err(Status::unauthenticated("No valid auth token")),
             }
-            _ => Err(Status::unauthenticated("No valid auth token")),
+        };
+
+        if !auth_limits.check(&format!("{}@{}", name, remote_addr_str)) {
+            debug!("GRPC [{}] from {} too many requests", name, remote_addr_str);
+            return Err(Status::resource_exhausted("Too many requests"));
         }
+        req.extensions_mut().insert(Caller { name, policy, rpcs });
+        Ok(req)
+    };
+    // Rejected requests never reach the service, record them here
+    let check_auth = move |req: Request<()>| -> Result<Request<()>, Status> {
+        // The bare address, like the http audit entries
+        let remote = req
+            .remote_addr()
+            .map(|remote_addr| remote_addr.ip().to_string())
+            .unwrap_or_default();
+        let pending = crate::audit::Pending::new("grpc", "auth", serde_json::Value::Null);
+        authenticate(req).inspect_err(|status| {
+            pending.finish("", &remote, audit::http_code(status.code()));
+        })
     };
 
-    tokio::spawn(async move {
-        loop {
-            let ret = rx.recv().await;
-            match ret {
-                Ok(_) => {}
-                Err(e) => {
-                    error!("{}", e);
-                    break;
-                }
+    let service = audit::Audited(service::Service { events, limits });
+    debug!("Start GRPC service {}", addr);
+    let mut builder = tonic::transport::Server::builder();
+    if !profile.tls_cert.is_empty() {
+        let config = tls_config(&profile);
+        let builder_tls = config.and_then(|config| builder.tls_config(config).map_err(|e| e.to_string()));
+        match builder_tls {
+            Ok(builder_tls) => builder = builder_tls,
+            Err(e) => {
+                error!("Couldn't load grpc TLS config, {}", e);
+                return;
             }
         }
-    });
-
-    let service = service::Service { tx };
-    debug!("Start GRPC service {}", addr);
-    let ret = tonic::transport::Server::builder()
+    }
+    let ret = builder
+        // Probes can't authenticate
+        .add_service(health_proto::health_server::HealthServer::new(health::Health))
+        .add_service(
+            reflection_proto::server_reflection_server::ServerReflectionServer::with_interceptor(
+                reflection::Reflection,
+                check_auth.clone(),
+            ),
+        )
         .add_service(zrapi::base_server::BaseServer::with_interceptor(
             service, check_auth,
         ))
@@ -129,6 +323,32 @@ async fn tokio_main(addr: String, password: String, acl: String) {
     }
 }
 
+/// `token <identity> [ttl] [rpc,rpc...] [policy]` mints a token for the gRPC server,
+/// the policy replaces command-policy for whoever holds it.
+pub fn api_command(args: &[&str]) -> Result<String, String> {
+    let usage = String::from("-ERR Usage: grpc token <identity> [ttl] [rpc,rpc...|*] [policy]");
+    if args.len() < 2 || !args[0].eq_ignore_ascii_case("token") {
+        return Err(usage);
+    }
+    let profile = GOLOBAS.profile.lock().unwrap().clone();
+    if profile.token_secret.is_empty() {
+        return Err(String::from("-ERR No token-secret configured"));
+    }
+    let ttl = match args.get(2) {
+        Some(ttl) => ttl.parse::<u64>().map_err(|_| usage.clone())?,
+        None => profile.token_ttl,
+    };
+    let mut claims = crate::token::Claims::new(args[1], TOKEN_AUDIENCE, ttl);
+    claims.rpcs = split_list(args.get(3).copied().unwrap_or("*"));
+    if let Some(policy) = args.get(4) {
+        if !crate::policy::exists(policy) {
+            return Err(format!("-ERR No such policy [{}]", policy));
+        }
+        claims.policy = policy.to_string();
+    }
+    crate::token::mint(&profile.token_secret, &claims)
+}
+
 pub fn shutdown() {
     let id = GOLOBAS.profile.lock().unwrap().event_bind_node;
     if id > 0 {
@@ -149,6 +369,7 @@ pub fn shutdown() {
             break;
         }
     }
+    *GOLOBAS.events.lock().unwrap() = None;
 }
 
 pub fn load_config(cfg: switch_xml_t) {
@@ -176,15 +397,79 @@ pub fn load_config(cfg: switch_xml_t) {
                     GOLOBAS.profile.lock().unwrap().password = val;
                 } else if var.eq_ignore_ascii_case("apply-inbound-acl") {
                     GOLOBAS.profile.lock().unwrap().apply_inbound_acl = val;
+                } else if var.eq_ignore_ascii_case("tls-cert") {
+                    GOLOBAS.profile.lock().unwrap().tls_cert = val;
+                } else if var.eq_ignore_ascii_case("tls-key") {
+                    GOLOBAS.profile.lock().unwrap().tls_key = val;
+                } else if var.eq_ignore_ascii_case("tls-client-ca") {
+                    GOLOBAS.profile.lock().unwrap().tls_client_ca = val;
+                } else if var.eq_ignore_ascii_case("event-replay-size") {
+                    GOLOBAS.profile.lock().unwrap().event_replay_size =
+                        val.parse::<usize>().unwrap_or(1024);
+                } else if var.eq_ignore_ascii_case("rate-limit") {
+                    GOLOBAS.profile.lock().unwrap().rate_limit = val.parse::<f64>().unwrap_or(0.0);
+                } else if var.eq_ignore_ascii_case("rate-burst") {
+                    GOLOBAS.profile.lock().unwrap().rate_burst = val.parse::<u32>().unwrap_or(0);
+                } else if var.eq_ignore_ascii_case("max-concurrent") {
+                    GOLOBAS.profile.lock().unwrap().max_concurrent =
+                        val.parse::<usize>().unwrap_or(0);
+                } else if var.eq_ignore_ascii_case("command-policy") {
+                    GOLOBAS.profile.lock().unwrap().command_policy = val;
+                } else if var.eq_ignore_ascii_case("password-auth") {
+                    GOLOBAS.profile.lock().unwrap().password_auth =
+                        if val.is_empty() { None } else { Some(switch_true(&val)) };
+                } else if var.eq_ignore_ascii_case("token-secret") {
+                    GOLOBAS.profile.lock().unwrap().token_secret = val;
+                } else if var.eq_ignore_ascii_case("token-public-key") {
+                    GOLOBAS.profile.lock().unwrap().token_public_key = val;
+                } else if var.eq_ignore_ascii_case("token-ttl") {
+                    GOLOBAS.profile.lock().unwrap().token_ttl = val.parse::<u64>().unwrap_or(3600);
                 } else if var.eq_ignore_ascii_case("enable") {
                     GOLOBAS.profile.lock().unwrap().enable = switch_true(&val);
-                } 
+                }
                 param = (*param).next;
             }
+
+            let tmp_str = CString::new("clients").unwrap();
+            let clients_tag = switch_sys::switch_xml_child(settings_tag, tmp_str.as_ptr());
+            if !clients_tag.is_null() {
+                let tmp_str = CString::new("client").unwrap();
+                let mut client_tag = switch_sys::switch_xml_child(clients_tag, tmp_str.as_ptr());
+                while !client_tag.is_null() {
+                    let tmp_str = CString::new("name").unwrap();
+                    let name = switch_to_string(switch_xml_attr_soft(client_tag, tmp_str.as_ptr()));
+                    let tmp_str = CString::new("policy").unwrap();
+                    let policy = switch_to_string(switch_xml_attr_soft(client_tag, tmp_str.as_ptr()));
+                    let tmp_str = CString::new("rpcs").unwrap();
+                    let mut rpcs = switch_to_string(switch_xml_attr_soft(client_tag, tmp_str.as_ptr()));
+                    if rpcs.is_empty() {
+                        rpcs = String::from("*");
+                    }
+
+                    if name.is_empty() {
+                        warn!("GRPC client without name, skipped");
+                    } else {
+                        GOLOBAS.profile.lock().unwrap().clients.push(Client {
+                            name,
+                            policy,
+                            rpcs: split_list(&rpcs),
+                        });
+                    }
+                    client_tag = (*client_tag).next;
+                }
+            }
         }
     }
 }
 
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|item| item.trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
 impl zrapi::Event {
     pub fn from(e: switch_sys::Event) -> zrapi::Event {
         zrapi::Event {
@@ -196,17 +481,21 @@ impl zrapi::Event {
             flags: e.flags,
             headers: e.headers,
             body: e.body,
+            sequence: 0,
+            gap: None,
         }
     }
 }
 
+/// Subscriber figures for the metrics, `None` when the server is not running.
+pub fn event_stats() -> Option<crate::metrics::EventStats> {
+    GOLOBAS.events.lock().unwrap().as_ref().map(|events| events.stats())
+}
+
 fn on_event(ev: switch_sys::Event) {
-    let tx = GOLOBAS.ev_tx.lock().unwrap().clone();
-    if tx.is_some() {
-        let ret = tx.unwrap().send(zrapi::Event::from(ev));
-        if let Err(e) = ret {
-            error!("{}", e);
-        }
+    let events = GOLOBAS.events.lock().unwrap().clone();
+    if let Some(events) = events {
+        events.publish(zrapi::Event::from(ev));
     }
 }
 
@@ -214,11 +503,9 @@ pub fn start(m: &switch_sys::Module, name: &str) {
     let profile = GOLOBAS.profile.lock().unwrap().clone();
     if profile.enable {
         let bind_uri = format!("{}:{:?}", profile.listen_ip, profile.listen_port);
-        let password = profile.password.clone();
-        let acl = profile.apply_inbound_acl.clone();
         thread::spawn(move || {
             *GOLOBAS.running.lock().unwrap() = true;
-            tokio_main(bind_uri, password, acl);
+            tokio_main(bind_uri, profile);
             *GOLOBAS.running.lock().unwrap() = false;
             debug!("Rustit GRPC service thread shutdown.");
         });
@@ -235,3 +522,40 @@ pub fn start(m: &switch_sys::Module, name: &str) {
         GOLOBAS.profile.lock().unwrap().event_bind_node = evnode;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn caller(rpcs: &[&str]) -> Caller {
+        Caller {
+            name: String::from("alice"),
+            policy: String::new(),
+            rpcs: rpcs.iter().map(|rpc| rpc.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn rpcs_restrict_the_methods() {
+        let caller = caller(&["Subscribe", "command"]);
+        assert!(caller.allows("Subscribe"));
+        assert!(caller.allows("Command"));
+        assert!(!caller.allows("SendMsg"));
+        assert!(!caller.allows("Session"));
+    }
+
+    #[test]
+    fn only_a_star_allows_all() {
+        assert!(!caller(&[]).allows("UnloadMod"));
+        assert!(!caller(&[]).allows("Subscribe"));
+        assert!(caller(&["*"]).allows("UnloadMod"));
+    }
+
+    #[test]
+    fn bearer_scheme_is_case_insensitive() {
+        assert_eq!(bearer("Bearer abc"), Some("abc"));
+        assert_eq!(bearer("bearer  abc "), Some("abc"));
+        assert_eq!(bearer("Basic abc"), None);
+        assert_eq!(bearer("abc"), None);
+    }
+}
@@ -0,0 +1,169 @@
+use super::reflection_proto::server_reflection_request::MessageRequest;
+use super::reflection_proto::server_reflection_response::MessageResponse;
+use super::reflection_proto::{
+    server_reflection_server, ErrorResponse, FileDescriptorResponse, ListServiceResponse,
+    ServerReflectionRequest, ServerReflectionResponse, ServiceResponse,
+};
+use lazy_static::lazy_static;
+use prost::Message;
+use prost_types::{DescriptorProto, FileDescriptorProto, FileDescriptorSet};
+use switch_sys::*;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Code, Request, Response, Status, Streaming};
+
+/// Services listed to clients.
+const SERVICES: [&str; 3] = [
+    "zrapi.Base",
+    "grpc.health.v1.Health",
+    "grpc.reflection.v1alpha.ServerReflection",
+];
+
+lazy_static! {
+    /// The proto files of the services and their imports, see build.rs
+    static ref FILES: Vec<FileDescriptorProto> =
+        FileDescriptorSet::decode(&include_bytes!("descriptor.bin")[..])
+            .map(|set| set.file)
+            .unwrap_or_else(|e| {
+                error!("GRPC reflection descriptors {}", e);
+                Vec::new()
+            });
+}
+
+/// Serves the proto files so clients like grpcurl need no copy of them.
+pub struct Reflection;
+
+fn message_declares(prefix: &str, message: &DescriptorProto, symbol: &str) -> bool {
+    let name = format!("{}.{}", prefix, message.name());
+    name == symbol
+        || message
+            .enum_type
+            .iter()
+            .any(|e| format!("{}.{}", name, e.name()) == symbol)
+        || message
+            .nested_type
+            .iter()
+            .any(|nested| message_declares(&name, nested, symbol))
+}
+
+/// Does `file` declare the fully qualified `symbol`, a service, method, message or enum.
+fn declares(file: &FileDescriptorProto, symbol: &str) -> bool {
+    let package = file.package();
+    let qualified = |name: &str| match package {
+        "" => name.to_string(),
+        package => format!("{}.{}", package, name),
+    };
+    file.service.iter().any(|service| {
+        let name = qualified(service.name());
+        name == symbol
+            || service
+                .method
+                .iter()
+                .any(|method| format!("{}.{}", name, method.name()) == symbol)
+    }) || file
+        .enum_type
+        .iter()
+        .any(|e| qualified(e.name()) == symbol)
+        || file.message_type.iter().any(|message| match package {
+            "" => message_declares("", message, &format!(".{}", symbol)),
+            package => message_declares(package, message, symbol),
+        })
+}
+
+/// `file` followed by everything it imports, each once.
+fn with_dependencies(file: &FileDescriptorProto) -> Vec<Vec<u8>> {
+    let mut seen = Vec::new();
+    let mut pending = vec![file.name().to_string()];
+    let mut encoded = Vec::new();
+    while let Some(name) = pending.pop() {
+        if seen.contains(&name) {
+            continue;
+        }
+        if let Some(file) = FILES.iter().find(|file| file.name() == name) {
+            encoded.push(file.encode_to_vec());
+            pending.extend(file.dependency.iter().cloned());
+        }
+        seen.push(name);
+    }
+    encoded
+}
+
+fn error_response(code: Code, message: String) -> MessageResponse {
+    MessageResponse::ErrorResponse(ErrorResponse {
+        error_code: code as i32,
+        error_message: message,
+    })
+}
+
+fn files_response(file: Option<&FileDescriptorProto>, what: &str) -> MessageResponse {
+    match file {
+        Some(file) => MessageResponse::FileDescriptorResponse(FileDescriptorResponse {
+            file_descriptor_proto: with_dependencies(file),
+        }),
+        None => error_response(Code::NotFound, format!("{} not found", what)),
+    }
+}
+
+fn answer(request: &MessageRequest) -> MessageResponse {
+    match request {
+        MessageRequest::ListServices(_) => MessageResponse::ListServicesResponse(ListServiceResponse {
+            service: SERVICES
+                .iter()
+                .map(|name| ServiceResponse {
+                    name: name.to_string(),
+                })
+                .collect(),
+        }),
+        MessageRequest::FileByFilename(name) => {
+            files_response(FILES.iter().find(|file| file.name() == name), name)
+        }
+        MessageRequest::FileContainingSymbol(symbol) => {
+            files_response(FILES.iter().find(|file| declares(file, symbol)), symbol)
+        }
+        // No extensions in these files
+        MessageRequest::FileContainingExtension(extension) => error_response(
+            Code::NotFound,
+            format!("{} extension {} not found", extension.containing_type, extension.extension_number),
+        ),
+        MessageRequest::AllExtensionNumbersOfType(name) => {
+            error_response(Code::NotFound, format!("{} extensions not found", name))
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl server_reflection_server::ServerReflection for Reflection {
+    type ServerReflectionInfoStream = ReceiverStream<Result<ServerReflectionResponse, Status>>;
+
+    async fn server_reflection_info(
+        &self,
+        request: Request<Streaming<ServerReflectionRequest>>,
+    ) -> Result<Response<Self::ServerReflectionInfoStream>, Status> {
+        let mut requests = request.into_inner();
+        let (tx, rx) = mpsc::channel(1);
+        tokio::spawn(async move {
+            loop {
+                let request = match requests.message().await {
+                    Ok(Some(request)) => request,
+                    Ok(None) => break,
+                    Err(status) => {
+                        debug!("GRPC reflection {}", status);
+                        break;
+                    }
+                };
+                let response = match &request.message_request {
+                    Some(message) => Ok(ServerReflectionResponse {
+                        valid_host: request.host.clone(),
+                        message_response: Some(answer(message)),
+                        original_request: Some(request),
+                    }),
+                    None => Err(Status::invalid_argument("Empty reflection request")),
+                };
+                if tx.send(response).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
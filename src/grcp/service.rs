@@ -1,5 +1,6 @@
 use switch_sys::*;
-use tokio::sync::{broadcast, mpsc};
+use crate::replay::Item;
+use tokio::sync::mpsc;
 use tokio_stream::wrappers::ReceiverStream;
 use tonic::{Request, Response, Status};
 use super::zrapi;
@@ -51,13 +52,168 @@ fn prost_to_serde_json(x: prost_types::Value) -> serde_json::Value {
     }
 }
 
+/// `PERMISSION_DENIED` unless the policy of the caller allows `command args`.
+fn check_policy<T>(request: &Request<T>, command: &str, args: &str) -> Result<(), Status> {
+    let caller = request
+        .extensions()
+        .get::<super::Caller>()
+        .ok_or_else(|| Status::unauthenticated("No caller"))?;
+    crate::policy::check(&caller.policy, &caller.name, command, args).map_err(Status::permission_denied)
+}
+
+/// The identity of the caller.
+fn caller_name<T>(request: &Request<T>) -> Result<String, Status> {
+    request
+        .extensions()
+        .get::<super::Caller>()
+        .map(|caller| caller.name.clone())
+        .ok_or_else(|| Status::unauthenticated("No caller"))
+}
+
+/// A request made within a session, on behalf of its caller.
+fn as_caller<T>(message: T, caller: &super::Caller) -> Request<T> {
+    let mut request = Request::new(message);
+    request.extensions_mut().insert(caller.clone());
+    request
+}
+
+impl From<crate::call::Error> for Status {
+    fn from(e: crate::call::Error) -> Status {
+        match e {
+            crate::call::Error::Invalid(e) => Status::invalid_argument(e),
+            crate::call::Error::Denied(e) => Status::permission_denied(e),
+            crate::call::Error::NotFound(e) => Status::not_found(e),
+            crate::call::Error::Failed(e) => Status::internal(e),
+        }
+    }
+}
+
+/// The call control client of the caller.
+fn call_client<T>(request: &Request<T>) -> Result<crate::call::Client, Status> {
+    let caller = request
+        .extensions()
+        .get::<super::Caller>()
+        .ok_or_else(|| Status::unauthenticated("No caller"))?;
+    Ok(crate::call::Client {
+        name: caller.name.clone(),
+        policy: caller.policy.clone(),
+    })
+}
+
+/// Run a call control function off the runtime, failures are returned as the status.
+async fn call_control<F>(f: F) -> Result<Response<zrapi::Reply>, Status>
+where
+    F: FnOnce() -> Result<serde_json::Value, crate::call::Error> + Send + 'static,
+{
+    let handle = tokio::task::spawn_blocking(f);
+    let data = handle.await.unwrap()?;
+    let reply = zrapi::Reply {
+        code: 200,
+        message: "OK".to_string(),
+        data: Some(serde_json_to_prost(data)),
+    };
+    Ok(Response::new(reply))
+}
+
+/// Run an inventory listing off the runtime.
+async fn inventory<F>(request: zrapi::ListRequest, f: F) -> Result<Response<zrapi::Reply>, Status>
+where
+    F: FnOnce(&crate::inventory::Query) -> Result<serde_json::Value, String> + Send + 'static,
+{
+    let query = crate::inventory::Query {
+        offset: request.offset as usize,
+        limit: request.limit as usize,
+        search: request.search,
+        filters: request.filters,
+    };
+    let handle = tokio::task::spawn_blocking(move || f(&query));
+    let res = handle.await.unwrap();
+    let reply = match res {
+        Err(e) => zrapi::Reply {
+            code: 500,
+            message: e,
+            data: None,
+        },
+        Ok(page) => zrapi::Reply {
+            code: 200,
+            message: "OK".to_string(),
+            data: Some(serde_json_to_prost(page)),
+        },
+    };
+    Ok(Response::new(reply))
+}
+
+#[derive(Clone)]
 pub struct Service {
-    pub tx: broadcast::Sender<zrapi::Event>,
+    pub events: std::sync::Arc<crate::replay::Replay<zrapi::Event>>,
+    pub limits: std::sync::Arc<crate::limit::Limits>,
+}
+
+impl Service {
+    /// A slot for a blocking execution, held until the reply is sent.
+    fn permit(&self) -> Result<crate::limit::Permit, Status> {
+        self.limits
+            .acquire()
+            .ok_or_else(|| Status::resource_exhausted("Too many concurrent requests"))
+    }
 }
 
 struct Topics {
     id: u32,
     subclass_name: String,
+    predicates: Vec<crate::filter::Predicate>,
+}
+
+fn parse_topics(topics: Vec<zrapi::Topic>) -> Result<Vec<Topics>, Status> {
+    let mut parsed = Vec::new();
+    for topic in topics {
+        let name = topic.event_name.to_ascii_uppercase();
+        let id = switch_sys::switch_event_types_t::from_str(&name);
+        if let Some(id) = id {
+            let mut predicates = Vec::new();
+            for filter in &topic.filters {
+                let predicate =
+                    crate::filter::Predicate::new(&filter.header, &filter.op, &filter.value)
+                        .map_err(Status::invalid_argument)?;
+                predicates.push(predicate);
+            }
+            parsed.push(Topics {
+                id: id.0,
+                subclass_name: topic.subclass,
+                predicates,
+            });
+        }
+    }
+    Ok(parsed)
+}
+
+fn topics_match(topics: &[Topics], e: &zrapi::Event) -> bool {
+    for topic in topics {
+        if !crate::filter::matches_all(&topic.predicates, &e.headers) {
+            continue;
+        }
+        if topic.id == switch_sys::switch_event_types_t::SWITCH_EVENT_ALL.0 {
+            return true;
+        } else if (topic.id == switch_sys::switch_event_types_t::SWITCH_EVENT_CUSTOM.0)
+            && (topic.subclass_name == e.subclass_name)
+        {
+            return true;
+        } else if topic.id == e.event_id {
+            return true;
+        }
+    }
+    false
+}
+
+/// Swap in `add` for the topics of the same event and subclass.
+fn update_topics(topics: &mut Vec<Topics>, add: Vec<Topics>, remove: bool) {
+    topics.retain(|topic| {
+        !add.iter()
+            .any(|other| other.id == topic.id && other.subclass_name == topic.subclass_name)
+    });
+    if !remove {
+        topics.extend(add);
+    }
 }
 
 #[tonic::async_trait]
@@ -77,45 +233,51 @@ impl super::zrapi::base_server::Base for Service {
         }
 
         info!("Got a subscriber from {}", remote_addr_str);
-        let mut topics: Vec<Topics> = Vec::new();
-        for topic in request.into_inner().topics {
-            let name = topic.event_name.to_ascii_uppercase();
-            let id = switch_sys::switch_event_types_t::from_str(&name);
-            if let Some(id) = id {
-                topics.push(Topics {
-                    id: id.0,
-                    subclass_name: topic.subclass,
-                });
+        let request = request.into_inner();
+        let headers = request.headers;
+        let last_sequence = Some(request.last_sequence).filter(|seq| *seq > 0);
+        let topics = parse_topics(request.topics)?;
+
+        let mut calls = None;
+        if !request.uuids.is_empty() {
+            calls = crate::filter::CallTracker::new(&request.uuids);
+            if calls.is_none() {
+                return Err(Status::not_found("No such channel"));
             }
         }
 
         let (tx, rx) = mpsc::channel(10);
-        let mut sub_rx = self.tx.subscribe();
+        let mut sub_rx = self.events.subscribe(last_sequence);
         tokio::spawn(async move {
             loop {
                 let v = sub_rx.recv().await;
                 match v {
-                    Err(e) => {
-                        error!("Event broadcast shutdown: {:?}", e);
+                    None => {
+                        error!("Event broadcast shutdown");
                         break;
                     }
-                    Ok(e) => {
-                        let mut pass = false;
-                        for topic in &topics {
-                            if topic.id == switch_sys::switch_event_types_t::SWITCH_EVENT_ALL.0 {
-                                pass = true;
-                                break;
-                            } else if (topic.id == switch_sys::switch_event_types_t::SWITCH_EVENT_CUSTOM.0)
-                                && (topic.subclass_name == e.subclass_name)
-                            {
-                                pass = true;
-                                break;
-                            } else if topic.id == e.event_id {
-                                pass = true;
-                                break;
+                    Some(Item::Gap(first, last)) => {
+                        warn!("Subscriber {} lost events {}..{}", remote_addr_str, first, last);
+                        let gap = zrapi::Event {
+                            sequence: last,
+                            gap: Some(zrapi::Gap { first, last }),
+                            ..Default::default()
+                        };
+                        if tx.send(Ok(gap)).await.is_err() {
+                            notice!("Subscriber disconnect from {}", remote_addr_str);
+                            break;
+                        }
+                    }
+                    Some(Item::Event(seq, mut e)) => {
+                        e.sequence = seq;
+                        if let Some(calls) = &mut calls {
+                            if !calls.track(e.event_id, &e.headers) {
+                                continue;
                             }
                         }
+                        let pass = (calls.is_some() && topics.is_empty()) || topics_match(&topics, &e);
                         if pass {
+                            crate::filter::project(&mut e.headers, &headers);
                             let send = tx.send(Ok(e)).await;
 
                             match send {
@@ -126,6 +288,10 @@ impl super::zrapi::base_server::Base for Service {
                                 _ => {}
                             }
                         }
+                        if calls.as_ref().is_some_and(|calls| calls.done()) {
+                            debug!("Subscriber {} calls hung up", remote_addr_str);
+                            break;
+                        }
                     }
                 };
             }
@@ -134,8 +300,196 @@ impl super::zrapi::base_server::Base for Service {
         Ok(Response::new(ReceiverStream::new(rx)))
     }
 
+    type SessionStream = ReceiverStream<Result<zrapi::SessionResponse, Status>>;
+
+    /// Commands, replies and events over one stream
+    async fn session(
+        &self,
+        request: Request<tonic::Streaming<zrapi::SessionRequest>>,
+    ) -> Result<Response<Self::SessionStream>, Status> {
+        let caller = request
+            .extensions()
+            .get::<super::Caller>()
+            .cloned()
+            .ok_or_else(|| Status::unauthenticated("No caller"))?;
+        let remote_addr_str = request
+            .remote_addr()
+            .map(|remote_addr| remote_addr.to_string())
+            .unwrap_or_default();
+        let remote_ip = request
+            .remote_addr()
+            .map(|remote_addr| remote_addr.ip().to_string())
+            .unwrap_or_default();
+
+        info!("Got a session from {}", remote_addr_str);
+        let mut frames = request.into_inner();
+        let (frame_tx, mut frame_rx) = mpsc::channel(10);
+        tokio::spawn(async move {
+            loop {
+                match frames.message().await {
+                    Ok(Some(frame)) => {
+                        if frame_tx.send(frame).await.is_err() {
+                            break;
+                        }
+                    }
+                    Ok(None) => break,
+                    Err(e) => {
+                        debug!("Session frame error {}", e);
+                        break;
+                    }
+                }
+            }
+        });
+
+        let service = self.clone();
+        let (tx, rx) = mpsc::channel(10);
+        let mut sub_rx = self.events.subscribe(None);
+        tokio::spawn(async move {
+            let mut topics: Vec<Topics> = Vec::new();
+            loop {
+                tokio::select! {
+                    frame = frame_rx.recv() => {
+                        let Some(frame) = frame else {
+                            notice!("Session closed from {}", remote_addr_str);
+                            break;
+                        };
+                        let id = frame.id;
+                        let reply = |code: i32, message: &str| zrapi::SessionResponse {
+                            id: id.clone(),
+                            response: Some(zrapi::session_response::Response::Reply(zrapi::Reply {
+                                code,
+                                message: message.to_string(),
+                                data: None,
+                            })),
+                        };
+                        use zrapi::session_request::Request as Frame;
+                        let (operation, args, request) = match frame.request {
+                            None => {
+                                if tx.send(Ok(reply(400, "Empty request"))).await.is_err() {
+                                    break;
+                                }
+                                continue;
+                            }
+                            Some(Frame::Subscribe(add)) => {
+                                let response = match parse_topics(add.topics) {
+                                    Ok(add) => {
+                                        update_topics(&mut topics, add, false);
+                                        reply(200, "OK")
+                                    }
+                                    Err(status) => reply(400, status.message()),
+                                };
+                                if tx.send(Ok(response)).await.is_err() {
+                                    break;
+                                }
+                                continue;
+                            }
+                            Some(Frame::Unsubscribe(remove)) => {
+                                let response = match parse_topics(remove.topics) {
+                                    Ok(remove) => {
+                                        update_topics(&mut topics, remove, true);
+                                        reply(200, "OK")
+                                    }
+                                    Err(status) => reply(400, status.message()),
+                                };
+                                if tx.send(Ok(response)).await.is_err() {
+                                    break;
+                                }
+                                continue;
+                            }
+                            Some(Frame::Command(req)) => ("Command", format!("{:?}", req), Frame::Command(req)),
+                            Some(Frame::SendMsg(req)) => ("SendMsg", format!("{:?}", req), Frame::SendMsg(req)),
+                            Some(Frame::Jsapi(req)) => ("JSAPI", format!("{:?}", req), Frame::Jsapi(req)),
+                        };
+                        let pending = crate::audit::Pending::new(
+                            "grpc",
+                            &format!("Session.{}", operation),
+                            serde_json::Value::String(args),
+                        );
+                        if !caller.allows(operation) {
+                            pending.finish(&caller.name, &remote_ip, 403);
+                            let message = format!("{} is not allowed", operation);
+                            if tx.send(Ok(reply(403, &message))).await.is_err() {
+                                break;
+                            }
+                            continue;
+                        }
+                        if !service.limits.check(&format!("{}@{}", caller.name, remote_ip)) {
+                            pending.finish(&caller.name, &remote_ip, 429);
+                            if tx.send(Ok(reply(429, "Too many requests"))).await.is_err() {
+                                break;
+                            }
+                            continue;
+                        }
+                        let service = service.clone();
+                        let caller = caller.clone();
+                        let remote_ip = remote_ip.clone();
+                        let tx = tx.clone();
+                        tokio::spawn(async move {
+                            let res = match request {
+                                Frame::Command(req) => service.command(as_caller(req, &caller)).await,
+                                Frame::SendMsg(req) => service.send_msg(as_caller(req, &caller)).await,
+                                Frame::Jsapi(req) => service.jsapi(as_caller(req, &caller)).await,
+                                _ => unreachable!(),
+                            };
+                            let reply = match res {
+                                Ok(reply) => reply.into_inner(),
+                                Err(status) => zrapi::Reply {
+                                    code: super::audit::http_code(status.code()),
+                                    message: status.message().to_string(),
+                                    data: None,
+                                },
+                            };
+                            pending.finish(&caller.name, &remote_ip, reply.code);
+                            let response = zrapi::SessionResponse {
+                                id,
+                                response: Some(zrapi::session_response::Response::Reply(reply)),
+                            };
+                            let _ = tx.send(Ok(response)).await;
+                        });
+                    }
+                    v = sub_rx.recv() => {
+                        let event = match v {
+                            None => {
+                                error!("Event broadcast shutdown");
+                                break;
+                            }
+                            Some(Item::Gap(_, _)) if topics.is_empty() => continue,
+                            Some(Item::Gap(first, last)) => {
+                                warn!("Session {} lost events {}..{}", remote_addr_str, first, last);
+                                zrapi::Event {
+                                    sequence: last,
+                                    gap: Some(zrapi::Gap { first, last }),
+                                    ..Default::default()
+                                }
+                            }
+                            Some(Item::Event(seq, mut e)) => {
+                                if !topics_match(&topics, &e) {
+                                    continue;
+                                }
+                                e.sequence = seq;
+                                e
+                            }
+                        };
+                        let response = zrapi::SessionResponse {
+                            id: String::new(),
+                            response: Some(zrapi::session_response::Response::Event(event)),
+                        };
+                        if tx.send(Ok(response)).await.is_err() {
+                            notice!("Session disconnect from {}", remote_addr_str);
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
     /// Command sends a single command to the server and returns a response Event.
     async fn command(&self, request: Request<super::zrapi::CommandRequest>) -> Result<Response<super::zrapi::Reply>, Status> {
+        check_policy(&request, &request.get_ref().command, &request.get_ref().args)?;
+        let _permit = self.permit()?;
         let req = request.into_inner();
         let mut cmd = req.command;
         let mut args = req.args;
@@ -197,6 +551,9 @@ impl super::zrapi::base_server::Base for Service {
 
     /// SendMsg sends messages to FreeSWITCH and returns a response.
     async fn send_msg(&self, request: Request<super::zrapi::SendMsgRequest>) -> Result<Response<super::zrapi::Reply>, Status> {
+        let (policy_cmd, policy_args) = crate::policy::sendmsg_command(&request.get_ref().headers);
+        check_policy(&request, &policy_cmd, &policy_args)?;
+        let _permit = self.permit()?;
         let req = request.into_inner();
         let handle = tokio::task::spawn_blocking(move || switch_sys::sendmsg(&req.uuid, req.headers));
         let res = handle.await.unwrap();
@@ -225,6 +582,10 @@ impl super::zrapi::base_server::Base for Service {
         &self,
         request: Request<super::zrapi::SendEventRequest>,
     ) -> Result<Response<super::zrapi::Reply>, Status> {
+        let (policy_cmd, policy_args) =
+            crate::policy::sendevent_command(request.get_ref().event_id, &request.get_ref().subclass_name);
+        check_policy(&request, &policy_cmd, &policy_args)?;
+        let _permit = self.permit()?;
         let req = request.into_inner();
         let handle = tokio::task::spawn_blocking(move || {
             switch_sys::sendevent(req.event_id, &req.subclass_name, req.headers, &req.body)
@@ -253,8 +614,10 @@ impl super::zrapi::base_server::Base for Service {
     /// reload xml
     async fn reload_xml(
         &self,
-        _request: Request<super::zrapi::ReloadXmlRequest>,
+        request: Request<super::zrapi::ReloadXmlRequest>,
     ) -> Result<Response<super::zrapi::Reply>, Status> {
+        check_policy(&request, "reloadxml", "")?;
+        let _permit = self.permit()?;
         // let _req: ReloadXmlRequest = request.into_inner();
         let handle = tokio::task::spawn_blocking(|| switch_sys::api_exec("reloadxml", ""));
         let res = handle.await.unwrap();
@@ -281,8 +644,10 @@ impl super::zrapi::base_server::Base for Service {
     /// Reload acl
     async fn reload_acl(
         &self,
-        _request: Request<super::zrapi::ReloadAclRequest>,
+        request: Request<super::zrapi::ReloadAclRequest>,
     ) -> Result<Response<super::zrapi::Reply>, Status> {
+        check_policy(&request, "reloadacl", "")?;
+        let _permit = self.permit()?;
         // let _req = request.into_inner();
         let handle = tokio::task::spawn_blocking(|| switch_sys::api_exec("reloadacl", ""));
         let res = handle.await.unwrap();
@@ -308,6 +673,8 @@ impl super::zrapi::base_server::Base for Service {
 
     /// Reload mod
     async fn reload_mod(&self, request: Request<super::zrapi::ModRequest>) -> Result<Response<super::zrapi::Reply>, Status> {
+        check_policy(&request, "reload", &request.get_ref().mod_name)?;
+        let _permit = self.permit()?;
         let req = request.into_inner();
         let mut cmd = "reload";
         let mut args = req.mod_name;
@@ -341,6 +708,8 @@ impl super::zrapi::base_server::Base for Service {
 
     /// Load mod
     async fn load_mod(&self, request: Request<super::zrapi::ModRequest>) -> Result<Response<super::zrapi::Reply>, Status> {
+        check_policy(&request, "load", &request.get_ref().mod_name)?;
+        let _permit = self.permit()?;
         let req = request.into_inner();
         let handle = tokio::task::spawn_blocking(move || switch_sys::api_exec("load", &req.mod_name));
         let res = handle.await.unwrap();
@@ -366,6 +735,8 @@ impl super::zrapi::base_server::Base for Service {
 
     /// Unload mod
     async fn unload_mod(&self, request: Request<super::zrapi::ModRequest>) -> Result<Response<super::zrapi::Reply>, Status> {
+        check_policy(&request, "unload", &request.get_ref().mod_name)?;
+        let _permit = self.permit()?;
         let req = request.into_inner();
         if req.mod_name.contains("mod_rustit") {
             let reply = super::zrapi::Reply {
@@ -400,6 +771,10 @@ impl super::zrapi::base_server::Base for Service {
 
     /// JSAPI
     async fn jsapi(&self, request: Request<super::zrapi::JsapiRequest>) -> Result<Response<super::zrapi::Reply>, Status> {
+        let data = prost_to_serde_json(request.get_ref().args.clone().unwrap_or_default());
+        let (policy_cmd, policy_args) = crate::policy::jsapi_command(&request.get_ref().command, &data);
+        check_policy(&request, &policy_cmd, &policy_args)?;
+        let _permit = self.permit()?;
         let req = request.into_inner();
 
         let args = req.args.unwrap_or_default();
@@ -469,4 +844,457 @@ impl super::zrapi::base_server::Base for Service {
             }
         }
     }
+
+    /// List cached storage files
+    async fn storage_list(
+        &self,
+        request: Request<super::zrapi::StorageListRequest>,
+    ) -> Result<Response<super::zrapi::Reply>, Status> {
+        check_policy(&request, "storage", &format!("list {} {}", request.get_ref().profile, request.get_ref().prefix))?;
+        let _permit = self.permit()?;
+        let req = request.into_inner();
+        let handle = tokio::task::spawn_blocking(move || {
+            crate::storage::cache_list(&req.profile, &req.prefix)
+        });
+        let res = handle.await.unwrap();
+        match res {
+            Err(e) => {
+                let reply = super::zrapi::Reply {
+                    code: 500,
+                    message: e,
+                    data: None,
+                };
+                Ok(Response::new(reply))
+            }
+            Ok(entries) => {
+                let reply = super::zrapi::Reply {
+                    code: 200,
+                    message: "OK".to_string(),
+                    data: Some(serde_json_to_prost(serde_json::json!(entries))),
+                };
+                Ok(Response::new(reply))
+            }
+        }
+    }
+
+    /// Purge cached storage files
+    async fn storage_purge(
+        &self,
+        request: Request<super::zrapi::StoragePurgeRequest>,
+    ) -> Result<Response<super::zrapi::Reply>, Status> {
+        check_policy(&request, "storage", &format!("purge {} {}", request.get_ref().profile, request.get_ref().url))?;
+        let _permit = self.permit()?;
+        let req = request.into_inner();
+        let handle = tokio::task::spawn_blocking(move || {
+            crate::storage::cache_purge(&req.profile, &req.url, req.prefix)
+        });
+        let res = handle.await.unwrap();
+        match res {
+            Err(e) => {
+                let reply = super::zrapi::Reply {
+                    code: 500,
+                    message: e,
+                    data: None,
+                };
+                Ok(Response::new(reply))
+            }
+            Ok(count) => {
+                let reply = super::zrapi::Reply {
+                    code: 200,
+                    message: "OK".to_string(),
+                    data: Some(serde_json_to_prost(serde_json::json!({ "purged": count }))),
+                };
+                Ok(Response::new(reply))
+            }
+        }
+    }
+
+    /// Prefetch storage files into the cache
+    async fn storage_prefetch(
+        &self,
+        request: Request<super::zrapi::StoragePrefetchRequest>,
+    ) -> Result<Response<super::zrapi::Reply>, Status> {
+        check_policy(&request, "storage", &format!("prefetch {} {}", request.get_ref().profile, request.get_ref().urls.join(",")))?;
+        let _permit = self.permit()?;
+        let req = request.into_inner();
+        let handle = tokio::task::spawn_blocking(move || {
+            crate::storage::cache_prefetch(&req.profile, &req.urls)
+        });
+        let res = handle.await.unwrap();
+        match res {
+            Err(e) => {
+                let reply = super::zrapi::Reply {
+                    code: 500,
+                    message: e,
+                    data: None,
+                };
+                Ok(Response::new(reply))
+            }
+            Ok(failed) => {
+                let reply = super::zrapi::Reply {
+                    code: 200,
+                    message: "OK".to_string(),
+                    data: Some(serde_json_to_prost(serde_json::json!({ "failed": failed }))),
+                };
+                Ok(Response::new(reply))
+            }
+        }
+    }
+
+    /// Re-upload unsynchronized recordings
+    async fn storage_resync(
+        &self,
+        request: Request<super::zrapi::StorageResyncRequest>,
+    ) -> Result<Response<super::zrapi::Reply>, Status> {
+        check_policy(&request, "storage", &format!("resync {}", request.get_ref().profile))?;
+        let _permit = self.permit()?;
+        let req = request.into_inner();
+        let handle = tokio::task::spawn_blocking(move || crate::storage::cache_resync(&req.profile));
+        let res = handle.await.unwrap();
+        match res {
+            Err(e) => {
+                let reply = super::zrapi::Reply {
+                    code: 500,
+                    message: e,
+                    data: None,
+                };
+                Ok(Response::new(reply))
+            }
+            Ok(count) => {
+                let reply = super::zrapi::Reply {
+                    code: 200,
+                    message: "OK".to_string(),
+                    data: Some(serde_json_to_prost(serde_json::json!({ "queued": count }))),
+                };
+                Ok(Response::new(reply))
+            }
+        }
+    }
+
+    /// Place a new call
+    async fn originate(
+        &self,
+        request: Request<super::zrapi::OriginateRequest>,
+    ) -> Result<Response<super::zrapi::Reply>, Status> {
+        let client = call_client(&request)?;
+        let _permit = self.permit()?;
+        let req = request.into_inner();
+        let request = crate::call::Originate {
+            endpoints: req.endpoints,
+            sequential: req.sequential,
+            variables: req.variables,
+            app: req.app,
+            app_args: req.app_args,
+            extension: req.extension,
+            dialplan: req.dialplan,
+            context: req.context,
+            caller_id_name: req.caller_id_name,
+            caller_id_number: req.caller_id_number,
+            timeout: req.timeout,
+        };
+        call_control(move || crate::call::originate(&client, &request)).await
+    }
+
+    /// Answer a channel
+    async fn answer(
+        &self,
+        request: Request<super::zrapi::ChannelRequest>,
+    ) -> Result<Response<super::zrapi::Reply>, Status> {
+        let client = call_client(&request)?;
+        let _permit = self.permit()?;
+        let req = request.into_inner();
+        let request = crate::call::Channel { uuid: req.uuid };
+        call_control(move || crate::call::answer(&client, &request)).await
+    }
+
+    /// Hang up a channel
+    async fn hangup(
+        &self,
+        request: Request<super::zrapi::HangupRequest>,
+    ) -> Result<Response<super::zrapi::Reply>, Status> {
+        let client = call_client(&request)?;
+        let _permit = self.permit()?;
+        let req = request.into_inner();
+        let request = crate::call::Hangup {
+            uuid: req.uuid,
+            cause: req.cause,
+        };
+        call_control(move || crate::call::hangup(&client, &request)).await
+    }
+
+    /// Transfer a channel to another extension
+    async fn transfer(
+        &self,
+        request: Request<super::zrapi::TransferRequest>,
+    ) -> Result<Response<super::zrapi::Reply>, Status> {
+        let client = call_client(&request)?;
+        let _permit = self.permit()?;
+        let req = request.into_inner();
+        let request = crate::call::Transfer {
+            uuid: req.uuid,
+            destination: req.destination,
+            dialplan: req.dialplan,
+            context: req.context,
+            leg: req.leg,
+        };
+        call_control(move || crate::call::transfer(&client, &request)).await
+    }
+
+    /// Bridge two channels
+    async fn bridge(
+        &self,
+        request: Request<super::zrapi::BridgeRequest>,
+    ) -> Result<Response<super::zrapi::Reply>, Status> {
+        let client = call_client(&request)?;
+        let _permit = self.permit()?;
+        let req = request.into_inner();
+        let request = crate::call::Bridge {
+            uuid: req.uuid,
+            other_uuid: req.other_uuid,
+        };
+        call_control(move || crate::call::bridge(&client, &request)).await
+    }
+
+    /// Park a channel
+    async fn park(
+        &self,
+        request: Request<super::zrapi::ChannelRequest>,
+    ) -> Result<Response<super::zrapi::Reply>, Status> {
+        let client = call_client(&request)?;
+        let _permit = self.permit()?;
+        let req = request.into_inner();
+        let request = crate::call::Channel { uuid: req.uuid };
+        call_control(move || crate::call::park(&client, &request)).await
+    }
+
+    /// Put a channel on or off hold
+    async fn hold(
+        &self,
+        request: Request<super::zrapi::HoldRequest>,
+    ) -> Result<Response<super::zrapi::Reply>, Status> {
+        let client = call_client(&request)?;
+        let _permit = self.permit()?;
+        let req = request.into_inner();
+        let request = crate::call::Hold {
+            uuid: req.uuid,
+            hold: !req.off,
+        };
+        call_control(move || crate::call::hold(&client, &request)).await
+    }
+
+    /// Play a file to a channel
+    async fn play(
+        &self,
+        request: Request<super::zrapi::PlayRequest>,
+    ) -> Result<Response<super::zrapi::Reply>, Status> {
+        let client = call_client(&request)?;
+        let _permit = self.permit()?;
+        let req = request.into_inner();
+        let request = crate::call::Play {
+            uuid: req.uuid,
+            path: req.path,
+            leg: req.leg,
+        };
+        call_control(move || crate::call::play(&client, &request)).await
+    }
+
+    /// Start or stop recording a channel
+    async fn record(
+        &self,
+        request: Request<super::zrapi::RecordRequest>,
+    ) -> Result<Response<super::zrapi::Reply>, Status> {
+        let client = call_client(&request)?;
+        let _permit = self.permit()?;
+        let req = request.into_inner();
+        let request = crate::call::Record {
+            uuid: req.uuid,
+            path: req.path,
+            start: !req.stop,
+            limit: req.limit,
+        };
+        call_control(move || crate::call::record(&client, &request)).await
+    }
+
+    /// Send DTMF to a channel
+    async fn send_dtmf(
+        &self,
+        request: Request<super::zrapi::DtmfRequest>,
+    ) -> Result<Response<super::zrapi::Reply>, Status> {
+        let client = call_client(&request)?;
+        let _permit = self.permit()?;
+        let req = request.into_inner();
+        let request = crate::call::Dtmf {
+            uuid: req.uuid,
+            digits: req.digits,
+            duration: req.duration,
+        };
+        call_control(move || crate::call::dtmf(&client, &request)).await
+    }
+
+    /// Set a channel variable
+    async fn set_variable(
+        &self,
+        request: Request<super::zrapi::SetVariableRequest>,
+    ) -> Result<Response<super::zrapi::Reply>, Status> {
+        let client = call_client(&request)?;
+        let _permit = self.permit()?;
+        let req = request.into_inner();
+        let request = crate::call::SetVariable {
+            uuid: req.uuid,
+            name: req.name,
+            value: req.value,
+        };
+        call_control(move || crate::call::set_variable(&client, &request)).await
+    }
+
+    /// Get a channel variable
+    async fn get_variable(
+        &self,
+        request: Request<super::zrapi::GetVariableRequest>,
+    ) -> Result<Response<super::zrapi::Reply>, Status> {
+        let client = call_client(&request)?;
+        let _permit = self.permit()?;
+        let req = request.into_inner();
+        let request = crate::call::GetVariable {
+            uuid: req.uuid,
+            name: req.name,
+        };
+        call_control(move || crate::call::get_variable(&client, &request)).await
+    }
+
+    /// Run a dialplan application on a channel
+    async fn execute(
+        &self,
+        request: Request<super::zrapi::ExecuteRequest>,
+    ) -> Result<Response<super::zrapi::Reply>, Status> {
+        let client = call_client(&request)?;
+        let _permit = self.permit()?;
+        let req = request.into_inner();
+        let request = crate::call::Execute {
+            uuid: req.uuid,
+            app: req.app,
+            args: req.args,
+            loops: req.loops,
+            event_lock: req.event_lock,
+        };
+        call_control(move || crate::call::execute(&client, &request)).await
+    }
+
+    /// Start a command in the background, returns the job at once
+    async fn submit_job(
+        &self,
+        request: Request<super::zrapi::JobRequest>,
+    ) -> Result<Response<super::zrapi::Reply>, Status> {
+        check_policy(&request, &request.get_ref().command, &request.get_ref().args)?;
+        let owner = caller_name(&request)?;
+        // Held by the job until its command finishes.
+        let permit = self.permit()?;
+        let req = request.into_inner();
+        let handle = tokio::task::spawn_blocking(move || {
+            crate::jobs::submit(&owner, &req.command, &req.args, &req.webhook, permit)
+        });
+        let res = handle.await.unwrap();
+        let reply = match res {
+            Err(e) => super::zrapi::Reply {
+                code: 500,
+                message: e,
+                data: None,
+            },
+            Ok(job) => super::zrapi::Reply {
+                code: 200,
+                message: "OK".to_string(),
+                data: Some(serde_json_to_prost(serde_json::json!(job))),
+            },
+        };
+        Ok(Response::new(reply))
+    }
+
+    /// Get a background job and its result
+    async fn get_job(
+        &self,
+        request: Request<super::zrapi::GetJobRequest>,
+    ) -> Result<Response<super::zrapi::Reply>, Status> {
+        let owner = caller_name(&request)?;
+        let req = request.into_inner();
+        let reply = match crate::jobs::get(&req.uuid, &owner) {
+            Some(job) => super::zrapi::Reply {
+                code: 200,
+                message: "OK".to_string(),
+                data: Some(serde_json_to_prost(serde_json::json!(job))),
+            },
+            None => super::zrapi::Reply {
+                code: 404,
+                message: format!("-ERR No such job [{}]", req.uuid),
+                data: None,
+            },
+        };
+        Ok(Response::new(reply))
+    }
+
+    /// System status
+    async fn system_status(
+        &self,
+        request: Request<super::zrapi::SystemStatusRequest>,
+    ) -> Result<Response<super::zrapi::Reply>, Status> {
+        check_policy(&request, "status", "")?;
+        let _permit = self.permit()?;
+        let handle = tokio::task::spawn_blocking(crate::inventory::status);
+        let status = handle.await.unwrap();
+        let reply = super::zrapi::Reply {
+            code: 200,
+            message: "OK".to_string(),
+            data: Some(serde_json_to_prost(status)),
+        };
+        Ok(Response::new(reply))
+    }
+
+    /// Active channels
+    async fn list_channels(
+        &self,
+        request: Request<super::zrapi::ListRequest>,
+    ) -> Result<Response<super::zrapi::Reply>, Status> {
+        check_policy(&request, "show", "channels")?;
+        let _permit = self.permit()?;
+        inventory(request.into_inner(), crate::inventory::channels).await
+    }
+
+    /// Bridged calls
+    async fn list_calls(
+        &self,
+        request: Request<super::zrapi::ListRequest>,
+    ) -> Result<Response<super::zrapi::Reply>, Status> {
+        check_policy(&request, "show", "calls")?;
+        let _permit = self.permit()?;
+        inventory(request.into_inner(), crate::inventory::calls).await
+    }
+
+    /// SIP registrations
+    async fn list_registrations(
+        &self,
+        request: Request<super::zrapi::ListRequest>,
+    ) -> Result<Response<super::zrapi::Reply>, Status> {
+        check_policy(&request, "show", "registrations")?;
+        let _permit = self.permit()?;
+        inventory(request.into_inner(), crate::inventory::registrations).await
+    }
+
+    /// Loaded modules
+    async fn list_modules(
+        &self,
+        request: Request<super::zrapi::ListRequest>,
+    ) -> Result<Response<super::zrapi::Reply>, Status> {
+        check_policy(&request, "show", "modules")?;
+        let _permit = self.permit()?;
+        inventory(request.into_inner(), crate::inventory::modules).await
+    }
+
+    /// Sofia gateways
+    async fn list_gateways(
+        &self,
+        request: Request<super::zrapi::ListRequest>,
+    ) -> Result<Response<super::zrapi::Reply>, Status> {
+        check_policy(&request, "sofia", "xmlstatus gateway")?;
+        let _permit = self.permit()?;
+        inventory(request.into_inner(), crate::inventory::gateways).await
+    }
 }
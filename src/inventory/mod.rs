@@ -0,0 +1,156 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::ffi::CString;
+use utoipa::IntoParams;
+use switch_sys::*;
+
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 1000;
+
+/// Gateway fields that are never returned.
+const HIDDEN_FIELDS: [&str; 1] = ["password"];
+
+/// Which rows of a list to return.
+#[derive(Debug, Default, Clone, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct Query {
+    #[serde(default)]
+    pub offset: usize,
+    /// 0 uses the default of 100, at most 1000
+    #[serde(default)]
+    pub limit: usize,
+    /// Case insensitive substring of any field
+    #[serde(default)]
+    pub search: String,
+    /// Fields that must be equal, as further query parameters
+    #[serde(default)]
+    #[param(style = Form, explode, value_type = Object)]
+    pub filters: HashMap<String, String>,
+}
+
+impl Query {
+    /// From URL query parameters, keys other than offset, limit and search are filters.
+    pub fn from_params(mut params: HashMap<String, String>) -> Query {
+        let offset = params
+            .remove("offset")
+            .and_then(|v| v.parse::<usize>().ok())
+            .unwrap_or(0);
+        let limit = params
+            .remove("limit")
+            .and_then(|v| v.parse::<usize>().ok())
+            .unwrap_or(0);
+        let search = params.remove("search").unwrap_or_default();
+        Query {
+            offset,
+            limit,
+            search,
+            filters: params,
+        }
+    }
+
+    fn matches(&self, row: &serde_json::Map<String, serde_json::Value>) -> bool {
+        for (name, expected) in &self.filters {
+            match row.get(name) {
+                Some(value) if value_str(value) == *expected => {}
+                _ => return false,
+            }
+        }
+        if self.search.is_empty() {
+            return true;
+        }
+        let search = self.search.to_lowercase();
+        row.values()
+            .any(|value| value_str(value).to_lowercase().contains(&search))
+    }
+
+    /// Filter and paginate `rows` into `{total, offset, limit, items}`.
+    fn page(&self, rows: Vec<serde_json::Value>) -> serde_json::Value {
+        let limit = match self.limit {
+            0 => DEFAULT_LIMIT,
+            limit => limit.min(MAX_LIMIT),
+        };
+        let rows: Vec<serde_json::Value> = rows
+            .into_iter()
+            .filter(|row| row.as_object().is_some_and(|row| self.matches(row)))
+            .collect();
+        let total = rows.len();
+        let items: Vec<serde_json::Value> =
+            rows.into_iter().skip(self.offset).take(limit).collect();
+        serde_json::json!({
+            "total": total,
+            "offset": self.offset,
+            "limit": limit,
+            "items": items,
+        })
+    }
+}
+
+fn value_str(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s) => s.clone(),
+        value => value.to_string(),
+    }
+}
+
+/// The rows of a `show <what> as json` command.
+fn show(what: &str) -> Result<Vec<serde_json::Value>, String> {
+    let reply = switch_sys::api_exec("show", &format!("{} as json", what))?;
+    let value: serde_json::Value = serde_json::from_str(&reply)
+        .map_err(|_| format!("-ERR show {}: {}", what, reply.trim()))?;
+    Ok(value
+        .get("rows")
+        .and_then(|rows| rows.as_array())
+        .cloned()
+        .unwrap_or_default())
+}
+
+pub fn status() -> serde_json::Value {
+    serde_json::json!(switch_sys::status())
+}
+
+pub fn channels(query: &Query) -> Result<serde_json::Value, String> {
+    Ok(query.page(show("channels")?))
+}
+
+pub fn calls(query: &Query) -> Result<serde_json::Value, String> {
+    Ok(query.page(show("calls")?))
+}
+
+pub fn registrations(query: &Query) -> Result<serde_json::Value, String> {
+    Ok(query.page(show("registrations")?))
+}
+
+pub fn modules(query: &Query) -> Result<serde_json::Value, String> {
+    Ok(query.page(show("modules")?))
+}
+
+/// Sofia gateways from `sofia xmlstatus gateway`.
+pub fn gateways(query: &Query) -> Result<serde_json::Value, String> {
+    let reply = switch_sys::api_exec("sofia", "xmlstatus gateway")?;
+    let mut rows = Vec::new();
+    unsafe {
+        let data = CString::new(reply.clone()).map_err(|e| e.to_string())?;
+        let xml = switch_xml_parse_str_dynamic(data.as_ptr() as *mut _, switch_bool_t::SWITCH_TRUE);
+        if xml.is_null() {
+            return Err(format!("-ERR sofia xmlstatus gateway: {}", reply.trim()));
+        }
+        let tmp_str = CString::new("gateway").unwrap();
+        let mut gateway = switch_xml_child(xml, tmp_str.as_ptr());
+        while !gateway.is_null() {
+            let mut row = serde_json::Map::new();
+            let mut field = (*gateway).child;
+            while !field.is_null() {
+                let name = switch_to_string((*field).name);
+                if !HIDDEN_FIELDS.contains(&name.as_str()) {
+                    let txt = switch_to_string((*field).txt);
+                    row.insert(name, serde_json::Value::String(txt));
+                }
+                field = (*field).ordered;
+            }
+            rows.push(serde_json::Value::Object(row));
+            gateway = (*gateway).next;
+        }
+        switch_xml_free(xml);
+    }
+    Ok(query.page(rows))
+}
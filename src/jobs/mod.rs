@@ -0,0 +1,221 @@
+use lazy_static::lazy_static;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use switch_sys::*;
+
+#[derive(Debug, Clone)]
+struct Profile {
+    /// Seconds a finished job is kept
+    retention: u64,
+    webhook_timeout: Duration,
+}
+
+impl Profile {
+    fn new() -> Profile {
+        Profile {
+            retention: 600,
+            webhook_timeout: Duration::from_millis(5000),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Running,
+    Done,
+    Failed,
+}
+
+/// A command running in the background.
+#[derive(Debug, Clone, Serialize)]
+pub struct Job {
+    pub uuid: String,
+    pub command: String,
+    pub args: String,
+    pub status: Status,
+    pub result: Option<String>,
+    /// Unix time in seconds
+    pub created: i64,
+    pub finished: Option<i64>,
+    #[serde(skip)]
+    webhook: String,
+    /// Identity that submitted it, the only one that may poll it
+    #[serde(skip)]
+    owner: String,
+}
+
+struct Global {
+    profile: Mutex<Profile>,
+    jobs: Mutex<HashMap<String, Job>>,
+}
+
+impl Global {
+    fn new() -> Global {
+        Global {
+            profile: Mutex::new(Profile::new()),
+            jobs: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+lazy_static! {
+    static ref GOLOBAS: Global = Global::new();
+}
+
+/// # Safety
+///
+/// `cfg` must be a valid `rustit.conf` xml node.
+pub unsafe fn load_config(cfg: switch_xml_t) {
+    unsafe {
+        let tmp_str = CString::new("jobs").unwrap();
+        let settings_tag = switch_sys::switch_xml_child(cfg, tmp_str.as_ptr());
+        if settings_tag.is_null() {
+            return;
+        }
+        let tmp_str = CString::new("param").unwrap();
+        let mut param = switch_sys::switch_xml_child(settings_tag, tmp_str.as_ptr());
+        while !param.is_null() {
+            let tmp_str = CString::new("name").unwrap();
+            let var = switch_sys::switch_xml_attr_soft(param, tmp_str.as_ptr());
+            let tmp_str = CString::new("value").unwrap();
+            let val = switch_sys::switch_xml_attr_soft(param, tmp_str.as_ptr());
+
+            let var = switch_sys::switch_to_string(var);
+            let val = switch_sys::switch_to_string(val);
+
+            if var.eq_ignore_ascii_case("retention") {
+                GOLOBAS.profile.lock().unwrap().retention = val.parse::<u64>().unwrap_or(600);
+            } else if var.eq_ignore_ascii_case("webhook-timeout") {
+                let timeout = val.parse::<u64>().unwrap_or(5000).clamp(100, 60000);
+                GOLOBAS.profile.lock().unwrap().webhook_timeout = Duration::from_millis(timeout);
+            }
+            param = (*param).next;
+        }
+    }
+}
+
+/// Forget finished jobs older than the retention.
+fn expire(jobs: &mut HashMap<String, Job>) {
+    let retention = GOLOBAS.profile.lock().unwrap().retention as i64;
+    let now = chrono::Utc::now().timestamp();
+    jobs.retain(|_, job| job.finished.is_none_or(|finished| now - finished < retention));
+}
+
+/// Start `command` on behalf of `owner` in the background and return the job at once.
+///
+/// The result is fired as a `BACKGROUND_JOB` event, posted to `webhook` if
+/// given, and kept for `GET /api/jobs/{id}` until the retention passes.
+/// `permit` is held until the command finishes.
+pub fn submit(
+    owner: &str,
+    command: &str,
+    args: &str,
+    webhook: &str,
+    permit: crate::limit::Permit,
+) -> Result<Job, String> {
+    if command.trim().is_empty() || command.contains(char::is_whitespace) {
+        return Err(format!("-ERR Invalid command [{}]", command));
+    }
+    if command.contains("unload") && args.contains("mod_rustit") {
+        return Err(String::from("-ERR Module mod_rustit is in use, cannot unload"));
+    }
+    if !webhook.is_empty() && !webhook.starts_with("http://") && !webhook.starts_with("https://") {
+        return Err(format!("-ERR Invalid webhook [{}]", webhook));
+    }
+
+    let uuid = switch_sys::api_exec("create_uuid", "")?.trim().to_string();
+    let job = Job {
+        uuid: uuid.clone(),
+        command: command.to_string(),
+        args: args.to_string(),
+        status: Status::Running,
+        result: None,
+        created: chrono::Utc::now().timestamp(),
+        finished: None,
+        webhook: webhook.to_string(),
+        owner: owner.to_string(),
+    };
+    {
+        let mut jobs = GOLOBAS.jobs.lock().unwrap();
+        expire(&mut jobs);
+        jobs.insert(uuid.clone(), job.clone());
+    }
+
+    let running = job.clone();
+    thread::Builder::new()
+        .name(format!("job-{}", uuid))
+        .spawn(move || {
+            let _permit = permit;
+            run(running)
+        })
+        .map_err(|e| {
+            GOLOBAS.jobs.lock().unwrap().remove(&uuid);
+            format!("-ERR {}", e)
+        })?;
+    Ok(job)
+}
+
+fn run(mut job: Job) {
+    debug!("Job {} started: {} {}", job.uuid, job.command, job.args);
+    let (status, result) = match switch_sys::api_exec(&job.command, &job.args) {
+        Ok(result) if result.starts_with("-ERR") || result.starts_with("-USAGE") => {
+            (Status::Failed, result)
+        }
+        Ok(result) => (Status::Done, result),
+        Err(e) => (Status::Failed, e),
+    };
+    job.status = status;
+    job.result = Some(result.clone());
+    job.finished = Some(chrono::Utc::now().timestamp());
+    GOLOBAS
+        .jobs
+        .lock()
+        .unwrap()
+        .insert(job.uuid.clone(), job.clone());
+    debug!("Job {} finished: {:?}", job.uuid, job.status);
+
+    let headers = HashMap::from([
+        (String::from("Job-UUID"), job.uuid.clone()),
+        (String::from("Job-Command"), job.command.clone()),
+        (String::from("Job-Command-Arg"), job.args.clone()),
+    ]);
+    if let Err(e) = switch_sys::sendevent(
+        switch_event_types_t::SWITCH_EVENT_BACKGROUND_JOB.0,
+        "",
+        headers,
+        &result,
+    ) {
+        error!("Job {} event: {}", job.uuid, e);
+    }
+
+    if !job.webhook.is_empty() {
+        let timeout = GOLOBAS.profile.lock().unwrap().webhook_timeout;
+        let client = reqwest::blocking::Client::builder()
+            .use_rustls_tls()
+            .timeout(timeout)
+            .build();
+        let ret = client.map_err(|e| e.to_string()).and_then(|client| {
+            client
+                .post(&job.webhook)
+                .json(&job)
+                .send()
+                .and_then(|response| response.error_for_status())
+                .map_err(|e| e.to_string())
+        });
+        if let Err(e) = ret {
+            warn!("Job {} webhook {} failed: {}", job.uuid, job.webhook, e);
+        }
+    }
+}
+
+/// The job `uuid` if `owner` submitted it.
+pub fn get(uuid: &str, owner: &str) -> Option<Job> {
+    let mut jobs = GOLOBAS.jobs.lock().unwrap();
+    expire(&mut jobs);
+    jobs.get(uuid).filter(|job| job.owner == owner).cloned()
+}
@@ -0,0 +1,149 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Idle buckets are dropped once there are more clients than this.
+const MAX_BUCKETS: usize = 4096;
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token buckets per client and a cap on concurrent blocking executions.
+pub struct Limits {
+    /// Requests per second per client, 0 is unlimited
+    rate: f64,
+    burst: f64,
+    buckets: Mutex<HashMap<String, Bucket>>,
+    permits: Option<Arc<Semaphore>>,
+}
+
+/// Held while a request executes, see `Limits::acquire`.
+pub struct Permit(#[allow(dead_code)] Option<OwnedSemaphorePermit>);
+
+impl Limits {
+    /// `burst` 0 defaults to one second worth of requests,
+    /// `max_concurrent` 0 is unlimited.
+    pub fn new(rate: f64, burst: u32, max_concurrent: usize) -> Arc<Limits> {
+        let rate = rate.max(0.0);
+        let burst = match burst {
+            0 => rate.ceil().max(1.0),
+            burst => burst as f64,
+        };
+        Arc::new(Limits {
+            rate,
+            burst,
+            buckets: Mutex::new(HashMap::new()),
+            permits: match max_concurrent {
+                0 => None,
+                n => Some(Arc::new(Semaphore::new(n))),
+            },
+        })
+    }
+
+    /// Take a token from the bucket of `client`, `false` if it is empty.
+    pub fn check(&self, client: &str) -> bool {
+        if self.rate <= 0.0 {
+            return true;
+        }
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_BUCKETS {
+            let (rate, burst) = (self.rate, self.burst);
+            buckets.retain(|_, bucket| {
+                let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+                bucket.tokens + elapsed * rate < burst
+            });
+        }
+        let bucket = buckets.entry(client.to_string()).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+        bucket.updated = now;
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+
+    /// A slot for a blocking execution, `None` when all are taken.
+    pub fn acquire(&self) -> Option<Permit> {
+        match &self.permits {
+            None => Some(Permit(None)),
+            Some(permits) => permits.clone().try_acquire_owned().ok().map(|p| Permit(Some(p))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn zero_rate_is_unlimited() {
+        let limits = Limits::new(0.0, 0, 0);
+        assert!((0..1000).all(|_| limits.check("a")));
+    }
+
+    #[test]
+    fn burst_is_spent_then_refused() {
+        let limits = Limits::new(1.0, 3, 0);
+        assert!(limits.check("a"));
+        assert!(limits.check("a"));
+        assert!(limits.check("a"));
+        assert!(!limits.check("a"));
+        // Buckets are per client
+        assert!(limits.check("b"));
+    }
+
+    #[test]
+    fn burst_defaults_to_one_second() {
+        let limits = Limits::new(2.5, 0, 0);
+        assert_eq!((0..10).filter(|_| limits.check("a")).count(), 3);
+    }
+
+    #[test]
+    fn tokens_refill_over_time() {
+        let limits = Limits::new(20.0, 1, 0);
+        assert!(limits.check("a"));
+        assert!(!limits.check("a"));
+        std::thread::sleep(Duration::from_millis(120));
+        assert!(limits.check("a"));
+        // Never more than the burst
+        assert!(!limits.check("a"));
+    }
+
+    #[test]
+    fn full_idle_buckets_are_evicted() {
+        let limits = Limits::new(1000.0, 2, 0);
+        for client in 0..MAX_BUCKETS {
+            assert!(limits.check(&client.to_string()));
+        }
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(limits.check("new"));
+        let buckets = limits.buckets.lock().unwrap();
+        assert!(buckets.len() < MAX_BUCKETS);
+        assert!(buckets.contains_key("new"));
+    }
+
+    #[test]
+    fn permits_run_out_and_come_back() {
+        let limits = Limits::new(0.0, 0, 2);
+        let first = limits.acquire();
+        let second = limits.acquire();
+        assert!(first.is_some() && second.is_some());
+        assert!(limits.acquire().is_none());
+        drop(first);
+        assert!(limits.acquire().is_some());
+
+        let unlimited = Limits::new(0.0, 0, 0);
+        let held: Vec<_> = (0..100).map(|_| unlimited.acquire()).collect();
+        assert!(held.iter().all(Option::is_some));
+    }
+}
@@ -0,0 +1,255 @@
+use lazy_static::lazy_static;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[derive(Debug, Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Gauge(AtomicI64);
+
+impl Gauge {
+    pub fn inc(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dec(&self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> i64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Latency buckets in seconds.
+const BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+#[derive(Debug, Default)]
+pub struct Histogram {
+    counts: [AtomicU64; BUCKETS.len()],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    pub fn observe(&self, elapsed: Duration) {
+        let seconds = elapsed.as_secs_f64();
+        for (i, le) in BUCKETS.iter().enumerate() {
+            if seconds <= *le {
+                self.counts[i].fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+}
+
+lazy_static! {
+    static ref XML_FETCH: Mutex<BTreeMap<String, Arc<Histogram>>> = Mutex::new(BTreeMap::new());
+    pub static ref CDR_POSTS: Counter = Counter::default();
+    pub static ref CDR_FAILURES: Counter = Counter::default();
+    /// CDRs being written or posted right now
+    pub static ref CDR_QUEUE: Gauge = Gauge::default();
+    pub static ref STORAGE_HITS: Counter = Counter::default();
+    pub static ref STORAGE_MISSES: Counter = Counter::default();
+    pub static ref STORAGE_DOWNLOAD_BYTES: Counter = Counter::default();
+    pub static ref STORAGE_PENDING_UPLOADS: Gauge = Gauge::default();
+}
+
+/// The XML fetch latency histogram of a binding.
+pub fn xml_fetch(binding: &str) -> Arc<Histogram> {
+    XML_FETCH
+        .lock()
+        .unwrap()
+        .entry(binding.to_string())
+        .or_default()
+        .clone()
+}
+
+/// Subscriber figures of an event listener.
+#[derive(Debug, Default, Clone)]
+pub struct EventStats {
+    pub subscribers: usize,
+    /// Events subscribers fell behind on and had replayed
+    pub lagged: u64,
+    /// Events subscribers lost for good
+    pub lost: u64,
+}
+
+/// Name, type, help and value of a per listener metric.
+type EventMetric = (&'static str, &'static str, &'static str, fn(&EventStats) -> u64);
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn metric<T: std::fmt::Display>(out: &mut String, name: &str, kind: &str, help: &str, value: T) {
+    header(out, name, kind, help);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+/// Everything in the Prometheus text exposition format.
+pub fn render(status: &switch_sys::SytemStatus, events: &[(&str, EventStats)]) -> String {
+    let mut out = String::new();
+
+    metric(&mut out, "rustit_ready", "gauge", "Whether the core is ready.", status.ready as u8);
+    metric(
+        &mut out,
+        "rustit_uptime_seconds",
+        "gauge",
+        "Seconds since the core started.",
+        status.uptime as f64 / 1_000_000.0,
+    );
+    metric(&mut out, "rustit_sessions_total", "counter", "Sessions since startup.", status.session_total);
+    metric(&mut out, "rustit_sessions_active", "gauge", "Active sessions.", status.session_active);
+    metric(&mut out, "rustit_sessions_peak", "gauge", "Peak active sessions.", status.session_peak);
+    metric(
+        &mut out,
+        "rustit_sessions_peak_5min",
+        "gauge",
+        "Peak active sessions in the last 5 minutes.",
+        status.session_peak_5min,
+    );
+    metric(&mut out, "rustit_sessions_limit", "gauge", "Maximum sessions.", status.session_limit);
+    metric(
+        &mut out,
+        "rustit_sessions_per_second",
+        "gauge",
+        "Sessions created in the last second.",
+        status.rate_current,
+    );
+    metric(
+        &mut out,
+        "rustit_sessions_per_second_limit",
+        "gauge",
+        "Maximum sessions per second.",
+        status.rate_max,
+    );
+    metric(
+        &mut out,
+        "rustit_sessions_per_second_peak",
+        "gauge",
+        "Peak sessions per second.",
+        status.rate_peak,
+    );
+    metric(
+        &mut out,
+        "rustit_sessions_per_second_peak_5min",
+        "gauge",
+        "Peak sessions per second in the last 5 minutes.",
+        status.rate_peak_5min,
+    );
+    metric(&mut out, "rustit_idle_cpu_percent", "gauge", "Idle CPU.", status.idle_cpu_used);
+    metric(
+        &mut out,
+        "rustit_idle_cpu_min_percent",
+        "gauge",
+        "Minimum idle CPU before new sessions are refused.",
+        status.idle_cpu_allowed,
+    );
+
+    let name = "rustit_xml_fetch_duration_seconds";
+    header(&mut out, name, "histogram", "XML fetch latency per binding.");
+    for (binding, histogram) in XML_FETCH.lock().unwrap().iter() {
+        let binding = escape(binding);
+        for (i, le) in BUCKETS.iter().enumerate() {
+            let count = histogram.counts[i].load(Ordering::Relaxed);
+            let _ = writeln!(out, "{}_bucket{{binding=\"{}\",le=\"{}\"}} {}", name, binding, le, count);
+        }
+        let count = histogram.count.load(Ordering::Relaxed);
+        let sum = histogram.sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+        let _ = writeln!(out, "{}_bucket{{binding=\"{}\",le=\"+Inf\"}} {}", name, binding, count);
+        let _ = writeln!(out, "{}_sum{{binding=\"{}\"}} {}", name, binding, sum);
+        let _ = writeln!(out, "{}_count{{binding=\"{}\"}} {}", name, binding, count);
+    }
+
+    metric(&mut out, "rustit_cdr_posts_total", "counter", "CDRs posted.", CDR_POSTS.get());
+    metric(
+        &mut out,
+        "rustit_cdr_failures_total",
+        "counter",
+        "CDRs that could not be posted.",
+        CDR_FAILURES.get(),
+    );
+    metric(
+        &mut out,
+        "rustit_cdr_queue_depth",
+        "gauge",
+        "CDRs waiting to be posted.",
+        CDR_QUEUE.get(),
+    );
+
+    metric(
+        &mut out,
+        "rustit_storage_cache_hits_total",
+        "counter",
+        "Storage files served from the cache.",
+        STORAGE_HITS.get(),
+    );
+    metric(
+        &mut out,
+        "rustit_storage_cache_misses_total",
+        "counter",
+        "Storage files downloaded.",
+        STORAGE_MISSES.get(),
+    );
+    metric(
+        &mut out,
+        "rustit_storage_download_bytes_total",
+        "counter",
+        "Bytes downloaded into the storage cache.",
+        STORAGE_DOWNLOAD_BYTES.get(),
+    );
+    metric(
+        &mut out,
+        "rustit_storage_pending_uploads",
+        "gauge",
+        "Recordings queued for upload.",
+        STORAGE_PENDING_UPLOADS.get(),
+    );
+
+    let stats: [EventMetric; 3] = [
+        ("rustit_event_subscribers", "gauge", "Connected event subscribers.", |s| s.subscribers as u64),
+        (
+            "rustit_event_lagged_total",
+            "counter",
+            "Events subscribers fell behind on and had replayed.",
+            |s| s.lagged,
+        ),
+        ("rustit_event_lost_total", "counter", "Events subscribers lost.", |s| s.lost),
+    ];
+    for (name, kind, help, value) in stats {
+        header(&mut out, name, kind, help);
+        for (listener, stats) in events {
+            let _ = writeln!(out, "{}{{listener=\"{}\"}} {}", name, listener, value(stats));
+        }
+    }
+    out
+}
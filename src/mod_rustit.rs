@@ -6,12 +6,36 @@ pub mod grcp;
 pub mod storage;
 pub mod xml;
 pub mod api;
+pub mod tls;
+pub mod filter;
+pub mod replay;
+pub mod call;
+pub mod jobs;
+pub mod inventory;
+pub mod metrics;
+pub mod limit;
+pub mod policy;
+pub mod audit;
+pub mod sounds;
+pub mod token;
 
 const MODULE_NAME: &str = "mod_rustit";
 
+const API_SYNTAX: &str =
+    "storage <list|purge|prefetch|resync> <profile> [url[*]|url,url...] | grpc token <identity> [ttl] [rpc,rpc...|*] [policy]";
+
 fn api_rustit(_session: &switch_sys::Session, cmd: String, stream: &switch_sys::Stream) -> switch_sys::switch_status_t {
     debug!("api rustit:{}", cmd);
-    stream.write("OK");
+    let args: Vec<&str> = cmd.split_whitespace().collect();
+    let response = match args.first() {
+        Some(&"storage") => storage::api_command(&args[1..]),
+        Some(&"grpc") => grcp::api_command(&args[1..]),
+        _ => Err(format!("-ERR Usage: rustit {}", API_SYNTAX)),
+    };
+    match response {
+        Ok(msg) => stream.write(&format!("{}\n", msg)),
+        Err(e) => stream.write(&format!("{}\n", e)),
+    }
     switch_status_t::SWITCH_STATUS_SUCCESS
 }
 
@@ -36,13 +60,17 @@ fn do_config() {
         cdr::load_config(cfg);
         storage::load_config(cfg);
         api::load_config(cfg);
+        jobs::load_config(cfg);
+        policy::load_config(cfg);
+        audit::load_config(cfg);
+        sounds::load_config(cfg);
         switch_sys::switch_xml_free(xml);
     }
 }
 
 fn zrs_mod_load(m: &switch_sys::Module) -> switch_status_t {
     do_config();
-    fsr_api!(m, "rustit", "rustit desc", "rustit syntax", api_rustit);
+    fsr_api!(m, "rustit", "rustit desc", API_SYNTAX, api_rustit);
     fsr_app!(
         m,
         "rustit",
@@ -67,6 +95,7 @@ fn zrs_mod_shutdown() -> switch_status_t {
     cdr::shutdown();
     storage::shutdown();
     api::shutdown();
+    audit::shutdown();
 
     let rt = Runtime::new().unwrap();
     rt.shutdown_timeout(tokio::time::Duration::from_millis(1000));
@@ -0,0 +1,330 @@
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::sync::RwLock;
+use switch_sys::*;
+
+/// One `<rule action="allow|deny" command="name|*" args="regex"/>`.
+#[derive(Debug, Clone)]
+struct Rule {
+    allow: bool,
+    /// Command name, `*` matches any
+    command: String,
+    /// Must match somewhere in the arguments when given
+    args: Option<regex::Regex>,
+}
+
+impl Rule {
+    fn matches(&self, command: &str, args: &str) -> bool {
+        (self.command == "*" || self.command.eq_ignore_ascii_case(command))
+            && self.args.as_ref().is_none_or(|re| re.is_match(args))
+    }
+}
+
+/// Rules are tried in order, the first match decides, otherwise the default.
+#[derive(Debug, Clone)]
+struct Policy {
+    default_allow: bool,
+    rules: Vec<Rule>,
+}
+
+lazy_static! {
+    static ref POLICIES: RwLock<HashMap<String, Policy>> = RwLock::new(HashMap::new());
+}
+
+/// # Safety
+///
+/// `cfg` must be a valid `rustit.conf` xml node.
+pub unsafe fn load_config(cfg: switch_xml_t) {
+    let mut policies = HashMap::new();
+    unsafe {
+        let tmp_str = CString::new("policies").unwrap();
+        let policies_tag = switch_sys::switch_xml_child(cfg, tmp_str.as_ptr());
+        if !policies_tag.is_null() {
+            let tmp_str = CString::new("policy").unwrap();
+            let mut policy_tag = switch_sys::switch_xml_child(policies_tag, tmp_str.as_ptr());
+            while !policy_tag.is_null() {
+                let tmp_str = CString::new("name").unwrap();
+                let name = switch_to_string(switch_xml_attr_soft(policy_tag, tmp_str.as_ptr()));
+                let tmp_str = CString::new("default").unwrap();
+                let default = switch_to_string(switch_xml_attr_soft(policy_tag, tmp_str.as_ptr()));
+                let mut policy = Policy {
+                    default_allow: !default.eq_ignore_ascii_case("deny"),
+                    rules: Vec::new(),
+                };
+
+                let tmp_str = CString::new("rule").unwrap();
+                let mut rule_tag = switch_sys::switch_xml_child(policy_tag, tmp_str.as_ptr());
+                while !rule_tag.is_null() {
+                    let tmp_str = CString::new("action").unwrap();
+                    let action = switch_to_string(switch_xml_attr_soft(rule_tag, tmp_str.as_ptr()));
+                    let tmp_str = CString::new("command").unwrap();
+                    let command = switch_to_string(switch_xml_attr_soft(rule_tag, tmp_str.as_ptr()));
+                    let tmp_str = CString::new("args").unwrap();
+                    let args = switch_to_string(switch_xml_attr_soft(rule_tag, tmp_str.as_ptr()));
+                    rule_tag = (*rule_tag).next;
+
+                    let allow = if action.eq_ignore_ascii_case("allow") {
+                        true
+                    } else if action.eq_ignore_ascii_case("deny") {
+                        false
+                    } else {
+                        warn!("Policy [{}] invalid rule action [{}], skipped", name, action);
+                        continue;
+                    };
+                    if command.is_empty() {
+                        warn!("Policy [{}] rule without command, skipped", name);
+                        continue;
+                    }
+                    let args = if args.is_empty() {
+                        None
+                    } else {
+                        match regex::Regex::new(&args) {
+                            Ok(re) => Some(re),
+                            // A broken deny rule denies the whole command rather than nothing
+                            Err(e) if !allow => {
+                                warn!("Policy [{}] invalid args [{}] {}, denying all of [{}]", name, args, e, command);
+                                None
+                            }
+                            Err(e) => {
+                                warn!("Policy [{}] invalid args [{}] {}, rule skipped", name, args, e);
+                                continue;
+                            }
+                        }
+                    };
+                    policy.rules.push(Rule {
+                        allow,
+                        command,
+                        args,
+                    });
+                }
+
+                if name.is_empty() {
+                    warn!("Policy without name, skipped");
+                } else {
+                    policies.insert(name, policy);
+                }
+                policy_tag = (*policy_tag).next;
+            }
+        }
+    }
+    *POLICIES.write().unwrap() = policies;
+}
+
+/// Commands that run their arguments as another command.
+const WRAPPERS: [&str; 2] = ["bgapi", "expand"];
+
+impl Policy {
+    /// The first rule matching `command args` decides, otherwise the default.
+    fn decide(&self, command: &str, args: &str) -> Option<&Rule> {
+        self.rules.iter().find(|rule| rule.matches(command, args))
+    }
+
+    fn check(&self, command: &str, args: &str) -> Result<(), String> {
+        let (mut cmd, mut rest) = (command.trim(), args.trim());
+        loop {
+            let rule = self.decide(cmd, rest);
+            if !rule.map_or(self.default_allow, |rule| rule.allow) {
+                return Err(cmd.to_string());
+            }
+            // `${api(args)}` runs any command anywhere in the arguments of expand,
+            // only a rule naming expand itself can allow that
+            if cmd.eq_ignore_ascii_case("expand")
+                && rest.contains("${")
+                && !rule.is_some_and(|rule| rule.command.eq_ignore_ascii_case("expand"))
+            {
+                return Err(cmd.to_string());
+            }
+            if !WRAPPERS.iter().any(|w| w.eq_ignore_ascii_case(cmd)) || rest.is_empty() {
+                return Ok(());
+            }
+            (cmd, rest) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+            rest = rest.trim();
+        }
+    }
+}
+
+/// May `who` run `command args` under `policy`, `Err` says why not.
+///
+/// An empty `policy` allows everything, an unknown one denies everything.
+/// The command a wrapper like `bgapi` runs has to be allowed too.
+pub fn check(policy: &str, who: &str, command: &str, args: &str) -> Result<(), String> {
+    if policy.is_empty() {
+        debug!("Policy: [{}] {} {} allowed, no policy", who, command, args);
+        return Ok(());
+    }
+    let policies = POLICIES.read().unwrap();
+    let rules = match policies.get(policy) {
+        Some(rules) => rules,
+        None => {
+            error!("Policy [{}] of [{}] does not exist, denying {} {}", policy, who, command, args);
+            return Err(format!("-ERR Command [{}] denied by policy [{}]", command, policy));
+        }
+    };
+    if let Err(cmd) = rules.check(command, args) {
+        warn!("Policy [{}]: [{}] {} {} denied", policy, who, command, args);
+        return Err(format!("-ERR Command [{}] denied by policy [{}]", cmd, policy));
+    }
+    info!("Policy [{}]: [{}] {} {} allowed", policy, who, command, args);
+    Ok(())
+}
+
+/// Whether a policy of that name is configured.
+pub fn exists(policy: &str) -> bool {
+    POLICIES.read().unwrap().contains_key(policy)
+}
+
+/// The api command a json api request runs, `fsapi` runs `data.cmd data.arg`.
+pub fn jsapi_command(command: &str, data: &serde_json::Value) -> (String, String) {
+    if command.eq_ignore_ascii_case("fsapi") {
+        let cmd = data.get("cmd").and_then(|v| v.as_str()).unwrap_or_default();
+        let arg = data.get("arg").and_then(|v| v.as_str()).unwrap_or_default();
+        return (cmd.to_string(), arg.to_string());
+    }
+    (command.to_string(), data.to_string())
+}
+
+/// The command a sendmsg runs: the application of an `execute`,
+/// otherwise `sendmsg <call-command>`. Event headers are case insensitive.
+pub fn sendmsg_command(headers: &HashMap<String, String>) -> (String, String) {
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.trim().to_string())
+            .unwrap_or_default()
+    };
+    let call_command = header("call-command");
+    let app = header("execute-app-name");
+    if call_command.eq_ignore_ascii_case("execute") && !app.is_empty() {
+        return (app, header("execute-app-arg"));
+    }
+    (String::from("sendmsg"), call_command)
+}
+
+/// The command a sendevent runs, `sendevent <NAME>` or `sendevent CUSTOM <subclass>`.
+pub fn sendevent_command(event_id: u32, subclass_name: &str) -> (String, String) {
+    let id = switch_event_types_t(event_id);
+    let name = id.event_id_str_name();
+    let mut args = name.strip_prefix("SWITCH_EVENT_").unwrap_or(name).to_string();
+    if id == switch_event_types_t::SWITCH_EVENT_CUSTOM {
+        args.push(' ');
+        args.push_str(subclass_name);
+    }
+    (String::from("sendevent"), args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(allow: bool, command: &str, args: Option<&str>) -> Rule {
+        Rule {
+            allow,
+            command: command.to_string(),
+            args: args.map(|args| regex::Regex::new(args).unwrap()),
+        }
+    }
+
+    fn remote() -> Policy {
+        Policy {
+            default_allow: true,
+            rules: vec![
+                rule(false, "system", None),
+                rule(false, "fsctl", Some(r"^\s*(shutdown|crash)")),
+            ],
+        }
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let policy = Policy {
+            default_allow: false,
+            rules: vec![
+                rule(false, "sofia", Some("^profile")),
+                rule(true, "sofia", None),
+                rule(false, "*", None),
+            ],
+        };
+        assert!(policy.check("sofia", "status").is_ok());
+        assert_eq!(policy.check("sofia", "profile internal restart"), Err(String::from("sofia")));
+        assert!(policy.check("status", "").is_err());
+
+        let allow_first = Policy {
+            default_allow: false,
+            rules: vec![rule(true, "*", None), rule(false, "system", None)],
+        };
+        assert!(allow_first.check("system", "id").is_ok());
+    }
+
+    #[test]
+    fn default_applies_when_nothing_matches() {
+        assert!(remote().check("status", "").is_ok());
+        assert!(remote().check("fsctl", "loglevel debug").is_ok());
+        assert!(remote().check("fsctl", " shutdown").is_err());
+
+        let read_only = Policy {
+            default_allow: false,
+            rules: vec![rule(true, "status", None)],
+        };
+        assert!(read_only.check("status", "").is_ok());
+        assert!(read_only.check("show", "channels").is_err());
+    }
+
+    #[test]
+    fn unknown_policy_denies_and_empty_allows() {
+        assert!(check("no-such-policy", "test", "status", "").is_err());
+        assert!(check("", "test", "system", "id").is_ok());
+    }
+
+    #[test]
+    fn wrapped_commands_are_checked() {
+        assert!(remote().check("bgapi", "status").is_ok());
+        assert_eq!(remote().check("bgapi", "system id"), Err(String::from("system")));
+        assert_eq!(remote().check("bgapi", "bgapi  system id"), Err(String::from("system")));
+        assert_eq!(remote().check("expand", "system id"), Err(String::from("system")));
+        assert_eq!(remote().check("BGAPI", "expand fsctl shutdown"), Err(String::from("fsctl")));
+        assert!(remote().check("bgapi", "expand echo ok").is_ok());
+    }
+
+    #[test]
+    fn expand_with_variables_needs_its_own_rule() {
+        assert!(remote().check("expand", "echo ${system(id)}").is_err());
+        assert!(remote().check("bgapi", "expand echo ${system(id)}").is_err());
+        assert!(remote().check("expand", "${sys}${tem} id").is_err());
+
+        let mut policy = remote();
+        policy.rules.insert(0, rule(true, "expand", Some(r"^echo \$\{domain\}$")));
+        assert!(policy.check("expand", "echo ${domain}").is_ok());
+        assert!(policy.check("expand", "echo ${system(id)}").is_err());
+    }
+
+    #[test]
+    fn sendmsg_runs_the_executed_app() {
+        let headers: HashMap<String, String> = [
+            ("Call-Command", "execute"),
+            ("execute-app-name", "system"),
+            ("execute-app-arg", "id"),
+        ]
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+        assert_eq!(sendmsg_command(&headers), (String::from("system"), String::from("id")));
+        assert!(remote().check("system", "id").is_err());
+
+        let hangup: HashMap<String, String> = [("call-command".to_string(), "hangup".to_string())].into();
+        assert_eq!(sendmsg_command(&hangup), (String::from("sendmsg"), String::from("hangup")));
+    }
+
+    #[test]
+    fn sendevent_is_named_after_the_event() {
+        assert_eq!(
+            sendevent_command(0, "my::event"),
+            (String::from("sendevent"), String::from("CUSTOM my::event"))
+        );
+        assert_eq!(
+            sendevent_command(switch_event_types_t::SWITCH_EVENT_CHANNEL_CREATE.0, ""),
+            (String::from("sendevent"), String::from("CHANNEL_CREATE"))
+        );
+    }
+}
@@ -0,0 +1,262 @@
+use switch_sys::*;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use tokio::sync::broadcast;
+
+/// What a subscriber receives, `Gap` covers the sequences that are lost.
+#[derive(Debug, Clone)]
+pub enum Item<T> {
+    Event(u64, T),
+    Gap(u64, u64),
+}
+
+struct Ring<T> {
+    seq: u64,
+    capacity: usize,
+    events: VecDeque<(u64, T)>,
+}
+
+impl<T: Clone> Ring<T> {
+    /// Everything after `last`, starting with a gap if some of it was dropped.
+    fn since(&self, last: u64) -> VecDeque<Item<T>> {
+        let mut items = VecDeque::new();
+        if let Some((oldest, _)) = self.events.front() {
+            if *oldest > last + 1 {
+                items.push_back(Item::Gap(last + 1, oldest - 1));
+            }
+        } else if self.seq > last {
+            items.push_back(Item::Gap(last + 1, self.seq));
+        }
+        for (seq, event) in self.events.iter() {
+            if *seq > last {
+                items.push_back(Item::Event(*seq, event.clone()));
+            }
+        }
+        items
+    }
+}
+
+/// Event broadcast that stamps every event with a sequence number and keeps
+/// the last `capacity` events, so subscribers can resume and recover from lag.
+pub struct Replay<T> {
+    ring: Mutex<Ring<T>>,
+    tx: broadcast::Sender<(u64, T)>,
+    lagged: AtomicU64,
+    lost: AtomicU64,
+}
+
+impl<T: Clone + Send + 'static> Replay<T> {
+    pub fn new(capacity: usize) -> Arc<Replay<T>> {
+        let (tx, _) = broadcast::channel(64);
+        // Start from the clock, so sequences keep growing across restarts.
+        let seq = chrono::Utc::now().timestamp_micros().max(0) as u64;
+        Arc::new(Replay {
+            ring: Mutex::new(Ring {
+                seq,
+                capacity: capacity.max(1),
+                events: VecDeque::new(),
+            }),
+            tx,
+            lagged: AtomicU64::new(0),
+            lost: AtomicU64::new(0),
+        })
+    }
+
+    pub fn publish(&self, event: T) -> u64 {
+        let mut ring = self.ring.lock().unwrap();
+        ring.seq += 1;
+        let seq = ring.seq;
+        if ring.events.len() >= ring.capacity {
+            ring.events.pop_front();
+        }
+        ring.events.push_back((seq, event.clone()));
+        // No subscribers is fine
+        let _ = self.tx.send((seq, event));
+        seq
+    }
+
+    pub fn stats(&self) -> crate::metrics::EventStats {
+        crate::metrics::EventStats {
+            subscribers: self.tx.receiver_count(),
+            lagged: self.lagged.load(Ordering::Relaxed),
+            lost: self.lost.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Subscribe to new events, or to everything after `last` if given.
+    pub fn subscribe(self: &Arc<Self>, last: Option<u64>) -> Subscriber<T> {
+        let ring = self.ring.lock().unwrap();
+        let rx = self.tx.subscribe();
+        let (pending, last) = match last {
+            Some(last) if last < ring.seq => (ring.since(last), last),
+            _ => (VecDeque::new(), ring.seq),
+        };
+        Subscriber {
+            replay: Arc::downgrade(self),
+            rx,
+            pending,
+            last,
+        }
+    }
+}
+
+/// Holds the replay weakly, so dropping the replay closes the subscribers.
+pub struct Subscriber<T> {
+    replay: Weak<Replay<T>>,
+    rx: broadcast::Receiver<(u64, T)>,
+    pending: VecDeque<Item<T>>,
+    last: u64,
+}
+
+impl<T: Clone + Send + 'static> Subscriber<T> {
+    /// Refill from the ring, `false` if the replay is gone.
+    fn catch_up(&mut self) -> bool {
+        match self.replay.upgrade() {
+            Some(replay) => {
+                self.pending = replay.ring.lock().unwrap().since(self.last);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The next event in sequence, `None` once the broadcast is closed.
+    pub async fn recv(&mut self) -> Option<Item<T>> {
+        loop {
+            if let Some(item) = self.pending.pop_front() {
+                match &item {
+                    Item::Event(seq, _) if *seq <= self.last => continue,
+                    Item::Event(seq, _) => self.last = *seq,
+                    Item::Gap(first, last) => {
+                        if let Some(replay) = self.replay.upgrade() {
+                            replay.lost.fetch_add(last - first + 1, Ordering::Relaxed);
+                        }
+                        self.last = *last
+                    }
+                }
+                return Some(item);
+            }
+            match self.rx.recv().await {
+                Ok((seq, event)) => {
+                    if seq <= self.last {
+                        continue;
+                    }
+                    if seq > self.last + 1 {
+                        // Missed some while catching up, the ring has them
+                        if !self.catch_up() {
+                            return None;
+                        }
+                        continue;
+                    }
+                    self.last = seq;
+                    return Some(Item::Event(seq, event));
+                }
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    debug!("Subscriber lagged {} events, replaying", n);
+                    if let Some(replay) = self.replay.upgrade() {
+                        replay.lagged.fetch_add(n, Ordering::Relaxed);
+                    }
+                    if !self.catch_up() {
+                        return None;
+                    }
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn next_event(sub: &mut Subscriber<u32>) -> (u64, u32) {
+        match sub.recv().await {
+            Some(Item::Event(seq, event)) => (seq, event),
+            other => panic!("expected an event, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn events_are_sequenced_in_order() {
+        let replay = Replay::new(8);
+        let mut sub = replay.subscribe(None);
+        let first = replay.publish(1);
+        let second = replay.publish(2);
+        assert_eq!(second, first + 1);
+        assert_eq!(next_event(&mut sub).await, (first, 1));
+        assert_eq!(next_event(&mut sub).await, (second, 2));
+    }
+
+    #[tokio::test]
+    async fn resume_replays_what_was_missed() {
+        let replay = Replay::new(8);
+        let first = replay.publish(1);
+        replay.publish(2);
+        replay.publish(3);
+        let mut sub = replay.subscribe(Some(first));
+        assert_eq!(next_event(&mut sub).await, (first + 1, 2));
+        assert_eq!(next_event(&mut sub).await, (first + 2, 3));
+
+        let later = replay.publish(4);
+        assert_eq!(next_event(&mut sub).await, (later, 4));
+    }
+
+    #[tokio::test]
+    async fn resume_past_the_ring_reports_a_gap() {
+        let replay = Replay::new(2);
+        let first = replay.publish(1);
+        for event in 2..=5 {
+            replay.publish(event);
+        }
+        let mut sub = replay.subscribe(Some(first));
+        match sub.recv().await {
+            Some(Item::Gap(from, to)) => assert_eq!((from, to), (first + 1, first + 2)),
+            other => panic!("expected a gap, got {:?}", other),
+        }
+        assert_eq!(next_event(&mut sub).await, (first + 3, 4));
+        assert_eq!(next_event(&mut sub).await, (first + 4, 5));
+        assert_eq!(replay.stats().lost, 2);
+    }
+
+    #[tokio::test]
+    async fn lagging_subscriber_catches_up_from_the_ring() {
+        let replay = Replay::new(256);
+        let mut sub = replay.subscribe(None);
+        let first = replay.publish(0);
+        for event in 1..200 {
+            replay.publish(event);
+        }
+        for event in 0..200 {
+            assert_eq!(next_event(&mut sub).await, (first + event as u64, event));
+        }
+        assert!(replay.stats().lagged > 0);
+        assert_eq!(replay.stats().lost, 0);
+    }
+
+    #[tokio::test]
+    async fn lag_past_the_ring_reports_a_gap() {
+        let replay = Replay::new(10);
+        let mut sub = replay.subscribe(None);
+        let first = replay.publish(0);
+        for event in 1..100 {
+            replay.publish(event);
+        }
+        match sub.recv().await {
+            Some(Item::Gap(from, to)) => assert_eq!((from, to), (first, first + 89)),
+            other => panic!("expected a gap, got {:?}", other),
+        }
+        for event in 90..100 {
+            assert_eq!(next_event(&mut sub).await, (first + event as u64, event));
+        }
+    }
+
+    #[tokio::test]
+    async fn dropping_the_replay_closes_subscribers() {
+        let replay = Replay::<u32>::new(4);
+        let mut sub = replay.subscribe(None);
+        drop(replay);
+        assert!(sub.recv().await.is_none());
+    }
+}
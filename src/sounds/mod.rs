@@ -0,0 +1,465 @@
+use lazy_static::lazy_static;
+use libc::c_char;
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use serde::Serialize;
+use std::ffi::CString;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+use switch_sys::*;
+use utoipa::ToSchema;
+
+/// Sample rates uploads can be converted to.
+const RATES: [u32; 7] = [8000, 16000, 22050, 24000, 32000, 44100, 48000];
+
+/// Why a sound file request failed.
+#[derive(Debug, Clone)]
+pub enum Error {
+    /// The request itself is wrong.
+    Invalid(String),
+    /// No such directory or file.
+    NotFound(String),
+    /// The file is there already.
+    Exists(String),
+    /// The file is larger than `max-size`.
+    TooLarge(String),
+    /// Reading, writing or converting failed.
+    Failed(String),
+}
+
+impl Error {
+    pub fn code(&self) -> i32 {
+        match self {
+            Error::Invalid(_) => 400,
+            Error::NotFound(_) => 404,
+            Error::Exists(_) => 409,
+            Error::TooLarge(_) => 413,
+            Error::Failed(_) => 500,
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Invalid(e)
+            | Error::NotFound(e)
+            | Error::Exists(e)
+            | Error::TooLarge(e)
+            | Error::Failed(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, Clone)]
+struct Profile {
+    /// Largest upload in bytes
+    max_size: u64,
+    /// Lower case extensions uploads may have
+    extensions: Vec<String>,
+    /// Sample rate uploads are converted to, 0 keeps them as they are
+    sample_rate: u32,
+    /// Named directories files are kept in
+    directories: Vec<(String, PathBuf)>,
+}
+
+impl Profile {
+    fn new() -> Profile {
+        Profile {
+            max_size: 20 * 1024 * 1024,
+            extensions: vec![String::from("wav"), String::from("mp3")],
+            sample_rate: 0,
+            directories: Vec::new(),
+        }
+    }
+}
+
+lazy_static! {
+    static ref PROFILE: Mutex<Profile> = Mutex::new(Profile::new());
+}
+
+/// # Safety
+///
+/// `cfg` must be a valid `rustit.conf` xml node.
+pub unsafe fn load_config(cfg: switch_xml_t) {
+    let mut profile = Profile::new();
+    unsafe {
+        let tmp_str = CString::new("sounds").unwrap();
+        let settings_tag = switch_sys::switch_xml_child(cfg, tmp_str.as_ptr());
+        if !settings_tag.is_null() {
+            let tmp_str = CString::new("param").unwrap();
+            let mut param = switch_sys::switch_xml_child(settings_tag, tmp_str.as_ptr());
+            while !param.is_null() {
+                let tmp_str = CString::new("name").unwrap();
+                let var = switch_sys::switch_xml_attr_soft(param, tmp_str.as_ptr());
+                let tmp_str = CString::new("value").unwrap();
+                let val = switch_sys::switch_xml_attr_soft(param, tmp_str.as_ptr());
+
+                let var = switch_sys::switch_to_string(var);
+                let val = switch_sys::switch_to_string(val);
+
+                if var.eq_ignore_ascii_case("max-size") {
+                    // In megabytes
+                    profile.max_size = val.parse::<u64>().unwrap_or(20).max(1) * 1024 * 1024;
+                } else if var.eq_ignore_ascii_case("extensions") {
+                    profile.extensions = val
+                        .split(',')
+                        .map(|ext| ext.trim().trim_start_matches('.').to_ascii_lowercase())
+                        .filter(|ext| !ext.is_empty())
+                        .collect();
+                } else if var.eq_ignore_ascii_case("sample-rate") {
+                    let rate = val.parse::<u32>().unwrap_or(0);
+                    if rate == 0 || RATES.contains(&rate) {
+                        profile.sample_rate = rate;
+                    } else {
+                        warn!("Invalid sounds sample-rate [{}], uploads are kept as they are", val);
+                    }
+                } else if var.eq_ignore_ascii_case("directory") {
+                    match val.split_once('=') {
+                        Some((name, path)) if !name.trim().is_empty() && !path.trim().is_empty() => {
+                            // Relative paths are under ${sounds_dir}
+                            let path = Path::new(&get_variable("sounds_dir")).join(path.trim());
+                            profile.directories.push((name.trim().to_string(), path));
+                        }
+                        _ => {
+                            warn!("Invalid sounds directory [{}], expecting name=path", val);
+                        }
+                    }
+                }
+                param = (*param).next;
+            }
+        }
+    }
+    *PROFILE.lock().unwrap() = profile;
+}
+
+/// A file in a sounds directory or storage cache.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct SoundFile {
+    /// Relative to the directory
+    pub name: String,
+    /// What to give `playback`
+    pub path: String,
+    pub size: u64,
+    /// RFC 3339, empty when unknown
+    pub modified: String,
+}
+
+impl SoundFile {
+    fn new(name: String, path: &Path) -> SoundFile {
+        let metadata = fs::metadata(path).ok();
+        SoundFile {
+            name,
+            path: path.display().to_string(),
+            size: metadata.as_ref().map_or(0, |metadata| metadata.len()),
+            modified: metadata
+                .and_then(|metadata| metadata.modified().ok())
+                .map(|modified| chrono::DateTime::<chrono::Local>::from(modified).to_rfc3339())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+fn directory(name: &str) -> Result<PathBuf> {
+    PROFILE
+        .lock()
+        .unwrap()
+        .directories
+        .iter()
+        .find(|(dir, _)| dir.eq_ignore_ascii_case(name))
+        .map(|(_, path)| path.clone())
+        .ok_or_else(|| Error::NotFound(format!("-ERR No such sounds directory [{}]", name)))
+}
+
+/// `name` as a relative path that stays inside its directory.
+fn relative(name: &str) -> Result<PathBuf> {
+    let path = Path::new(name.trim_matches('/'));
+    let valid = path.components().all(|component| match component {
+        Component::Normal(part) => !part.to_string_lossy().starts_with('.'),
+        _ => false,
+    });
+    if name.trim_matches('/').is_empty() || !valid {
+        return Err(Error::Invalid(format!("-ERR Invalid file name [{}]", name)));
+    }
+    Ok(path.to_path_buf())
+}
+
+fn extension(name: &str) -> String {
+    Path::new(name)
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default()
+}
+
+/// The largest upload in bytes.
+pub fn max_size() -> u64 {
+    PROFILE.lock().unwrap().max_size
+}
+
+/// Whether a file called `name` may be uploaded at all.
+pub fn check(name: &str) -> Result<()> {
+    let file_name = Path::new(name)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    relative(&file_name)?;
+    let ext = extension(name);
+    let extensions = PROFILE.lock().unwrap().extensions.clone();
+    if !extensions.contains(&ext) {
+        return Err(Error::Invalid(format!(
+            "-ERR Extension [{}] not allowed, expecting one of {}",
+            ext,
+            extensions.join(",")
+        )));
+    }
+    Ok(())
+}
+
+/// The sample rate an upload asking for `rate` ends up with, `None` keeps it.
+fn target_rate(rate: u32) -> Result<Option<u32>> {
+    let rate = match rate {
+        0 => PROFILE.lock().unwrap().sample_rate,
+        rate => rate,
+    };
+    match rate {
+        0 => Ok(None),
+        rate if RATES.contains(&rate) => Ok(Some(rate)),
+        rate => Err(Error::Invalid(format!("-ERR Unsupported sample rate [{}]", rate))),
+    }
+}
+
+/// An upload waiting in `${temp_dir}`, removed unless it was stored.
+pub struct Staged {
+    pub path: PathBuf,
+    /// The name it was uploaded as
+    pub name: String,
+}
+
+impl Staged {
+    pub fn new(name: &str) -> Staged {
+        let random: String = thread_rng()
+            .sample_iter(Alphanumeric)
+            .take(20)
+            .map(char::from)
+            .collect();
+        let path = Path::new(&get_variable("temp_dir"))
+            .join(format!("rustit_upload_{}.{}", random, extension(name)));
+        Staged {
+            path,
+            name: name.to_string(),
+        }
+    }
+}
+
+impl Drop for Staged {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Rename, or copy when `to` is on another file system.
+fn move_file(from: &Path, to: &Path) -> std::io::Result<()> {
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    fs::copy(from, to)?;
+    fs::remove_file(from)
+}
+
+/// Convert `src` into a mono wav file at `rate` through the FreeSWITCH file interfaces,
+/// so anything `playback` can read can be converted.
+fn convert(src: &Path, dst: &Path, rate: u32) -> Result<()> {
+    let src_str = CString::new(src.display().to_string()).unwrap();
+    let dst_str = CString::new(dst.display().to_string()).unwrap();
+    let mut input: switch_file_handle_t = Default::default();
+    let mut output: switch_file_handle_t = Default::default();
+    unsafe {
+        let status = switch_core_perform_file_open(
+            concat!(file!(), '\0').as_ptr() as *const c_char,
+            std::ptr::null_mut(),
+            line!() as libc::c_int,
+            &mut input,
+            src_str.as_ptr(),
+            1,
+            rate,
+            switch_file_flag_enum_t::SWITCH_FILE_FLAG_READ.0
+                | switch_file_flag_enum_t::SWITCH_FILE_DATA_SHORT.0,
+            std::ptr::null_mut(),
+        );
+        if status != switch_status_t::SWITCH_STATUS_SUCCESS {
+            return Err(Error::Invalid(String::from("-ERR Unreadable audio file")));
+        }
+        let status = switch_core_perform_file_open(
+            concat!(file!(), '\0').as_ptr() as *const c_char,
+            std::ptr::null_mut(),
+            line!() as libc::c_int,
+            &mut output,
+            dst_str.as_ptr(),
+            1,
+            rate,
+            switch_file_flag_enum_t::SWITCH_FILE_FLAG_WRITE.0
+                | switch_file_flag_enum_t::SWITCH_FILE_DATA_SHORT.0,
+            std::ptr::null_mut(),
+        );
+        if status != switch_status_t::SWITCH_STATUS_SUCCESS {
+            switch_core_file_close(&mut input);
+            return Err(Error::Failed(format!("-ERR Couldn't create {}", dst.display())));
+        }
+
+        // Room for multi channel reads before they are mixed down
+        let mut buf = vec![0i16; 1024 * 8];
+        let mut failed = false;
+        loop {
+            let mut len: switch_size_t = 1024;
+            let status = switch_core_file_read(&mut input, buf.as_mut_ptr() as *mut _, &mut len);
+            if status != switch_status_t::SWITCH_STATUS_SUCCESS || len == 0 {
+                break;
+            }
+            let status = switch_core_file_write(&mut output, buf.as_mut_ptr() as *mut _, &mut len);
+            if status != switch_status_t::SWITCH_STATUS_SUCCESS {
+                failed = true;
+                break;
+            }
+        }
+        switch_core_file_close(&mut input);
+        switch_core_file_close(&mut output);
+        if failed {
+            let _ = fs::remove_file(dst);
+            return Err(Error::Failed(format!("-ERR Couldn't write {}", dst.display())));
+        }
+    }
+    Ok(())
+}
+
+/// Where a stored upload ends up relative to its directory,
+/// converted files are always wav.
+fn stored_name(folder: &str, staged: &Staged, rate: Option<u32>) -> Result<PathBuf> {
+    let file_name = Path::new(&staged.name)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let mut name = match folder.trim_matches('/') {
+        "" => relative(&file_name)?,
+        folder => relative(folder)?.join(relative(&file_name)?),
+    };
+    if rate.is_some() {
+        name.set_extension("wav");
+    }
+    Ok(name)
+}
+
+/// Put `staged` into the sounds directory `dir` under `folder`,
+/// converted to `rate` (0 is the configured sample-rate).
+pub fn store(dir: &str, folder: &str, staged: &Staged, rate: u32, overwrite: bool) -> Result<SoundFile> {
+    let root = directory(dir)?;
+    let rate = target_rate(rate)?;
+    let name = stored_name(folder, staged, rate)?;
+    let path = root.join(&name);
+    if path.exists() && !overwrite {
+        return Err(Error::Exists(format!("-ERR {} exists", name.display())));
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| Error::Failed(format!("-ERR {}", e)))?;
+    }
+    match rate {
+        Some(rate) => {
+            // Converted next to the target, so a failure leaves the old file alone
+            let converted = path.with_extension("rustit_part.wav");
+            convert(&staged.path, &converted, rate)?;
+            move_file(&converted, &path).map_err(|e| {
+                let _ = fs::remove_file(&converted);
+                Error::Failed(format!("-ERR {}", e))
+            })?;
+        }
+        None => {
+            move_file(&staged.path, &path).map_err(|e| Error::Failed(format!("-ERR {}", e)))?;
+        }
+    }
+    notice!("Stored sound file {}", path.display());
+    Ok(SoundFile::new(name.display().to_string(), &path))
+}
+
+/// Put `staged` into the cache of the storage profile `storage` under `folder`,
+/// it is uploaded to the storage server like a recording.
+pub fn store_storage(storage: &str, folder: &str, staged: &Staged, rate: u32) -> Result<SoundFile> {
+    let rate = target_rate(rate)?;
+    let name = stored_name(folder, staged, rate)?.display().to_string();
+    let converted;
+    let file = match rate {
+        Some(rate) => {
+            converted = Staged::new("converted.wav");
+            convert(&staged.path, &converted.path, rate)?;
+            &converted.path
+        }
+        None => &staged.path,
+    };
+    let size = fs::metadata(file).map(|metadata| metadata.len()).unwrap_or(0);
+    let path = crate::storage::cache_store(storage, &name, file).map_err(|e| {
+        if e.contains("No such storage") {
+            Error::NotFound(e)
+        } else {
+            Error::Failed(e)
+        }
+    })?;
+    notice!("Stored sound file {}", path);
+    Ok(SoundFile {
+        name,
+        path,
+        size,
+        modified: chrono::Local::now().to_rfc3339(),
+    })
+}
+
+fn walk(root: &Path, dir: &Path, files: &mut Vec<SoundFile>) -> std::io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let hidden = path
+            .file_name()
+            .is_none_or(|name| name.to_string_lossy().starts_with('.'));
+        if hidden {
+            continue;
+        }
+        if path.is_dir() {
+            walk(root, &path, files)?;
+        } else if let Ok(name) = path.strip_prefix(root) {
+            files.push(SoundFile::new(name.display().to_string(), &path));
+        }
+    }
+    Ok(())
+}
+
+/// Every file of the sounds directory `dir`, optionally only under `folder`.
+pub fn list(dir: &str, folder: &str) -> Result<Vec<SoundFile>> {
+    let root = directory(dir)?;
+    let start = match folder.trim_matches('/') {
+        "" => root.clone(),
+        folder => root.join(relative(folder)?),
+    };
+    let mut files = Vec::new();
+    match walk(&root, &start, &mut files) {
+        Ok(()) => (),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+        Err(e) => return Err(Error::Failed(format!("-ERR {}", e))),
+    }
+    files.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(files)
+}
+
+/// The path of the file `name` in the sounds directory `dir`.
+pub fn find(dir: &str, name: &str) -> Result<PathBuf> {
+    let path = directory(dir)?.join(relative(name)?);
+    if !path.is_file() {
+        return Err(Error::NotFound(format!("-ERR No such file [{}]", name)));
+    }
+    Ok(path)
+}
+
+pub fn delete(dir: &str, name: &str) -> Result<()> {
+    let path = find(dir, name)?;
+    fs::remove_file(&path).map_err(|e| Error::Failed(format!("-ERR {}", e)))?;
+    notice!("Deleted sound file {}", path.display());
+    Ok(())
+}
@@ -7,14 +7,18 @@ use std::path;
 use std::path::Path;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::sync::Condvar;
 use std::sync::Mutex;
 use std::thread;
+use std::time::Duration;
+use std::time::SystemTime;
 
 use switch_sys::*;
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
 use redb::{ReadableTable, TableDefinition};
 use reqwest::header as rh;
+use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc;
 use tokio_util::codec::BytesCodec;
 use tokio_util::codec::FramedRead;
@@ -34,6 +38,37 @@ pub struct CacheRecord {
     /// The value of the whether it has been uploaded to the server
     #[prost(bool, tag = "4")]
     pub synchronized: bool,
+    /// Extra form fields sent with the upload.
+    #[prost(map = "string, string", tag = "5")]
+    pub fields: HashMap<String, String>,
+    /// Extra headers sent with the upload.
+    #[prost(map = "string, string", tag = "6")]
+    pub headers: HashMap<String, String>,
+    /// The hex md5 digest of the cached response body.
+    #[prost(string, optional, tag = "7")]
+    pub md5: Option<String>,
+    /// The size of the cached response body.
+    #[prost(uint64, tag = "8")]
+    pub size: u64,
+}
+
+/// What the admin commands report about a cached URL.
+#[derive(Debug, Clone, Serialize)]
+pub struct CacheEntry {
+    /// The cached URL.
+    pub url: String,
+    /// The path to the cached file on disk.
+    pub path: String,
+    /// The size of the cached file, 0 if it is missing.
+    pub size: u64,
+    /// The value of the Last-Modified header in the original response.
+    pub last_modified: Option<String>,
+    /// The value of the Etag header in the original response.
+    pub etag: Option<String>,
+    /// The value of the whether it has been uploaded to the server
+    pub synchronized: bool,
+    /// The hex md5 digest of the downloaded file.
+    pub md5: Option<String>,
 }
 
 pub struct Event {
@@ -42,6 +77,69 @@ pub struct Event {
     pub path: String,
     /// The url to update
     pub url: String,
+    /// Extra form fields sent with the upload.
+    pub fields: HashMap<String, String>,
+    /// Extra headers sent with the upload.
+    pub headers: HashMap<String, String>,
+}
+
+/// How recordings are uploaded to the storage server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UploadMethod {
+    /// POST a multipart form, the fields are sent as form fields.
+    Multipart,
+    /// PUT the raw file, the fields are sent as query parameters.
+    Put,
+    /// POST the fields as JSON to get a presigned url, then PUT the raw file to it.
+    Presigned,
+}
+
+impl UploadMethod {
+    pub fn from_str(value: &str) -> Option<UploadMethod> {
+        if value.eq_ignore_ascii_case("multipart") || value.eq_ignore_ascii_case("post") {
+            Some(UploadMethod::Multipart)
+        } else if value.eq_ignore_ascii_case("put") {
+            Some(UploadMethod::Put)
+        } else if value.eq_ignore_ascii_case("presigned") {
+            Some(UploadMethod::Presigned)
+        } else {
+            None
+        }
+    }
+}
+
+/// Upload settings of a storage profile.
+#[derive(Debug, Clone)]
+pub struct Upload {
+    pub method: UploadMethod,
+    /// The multipart form field that carries the file.
+    pub field: String,
+}
+
+impl Upload {
+    pub fn new() -> Upload {
+        Upload {
+            method: UploadMethod::Multipart,
+            field: String::from("file"),
+        }
+    }
+}
+
+/// The reply of the storage server to a presigned url request.
+#[derive(Debug, Deserialize)]
+struct Presigned {
+    url: String,
+    #[serde(default)]
+    method: Option<String>,
+    #[serde(default)]
+    headers: HashMap<String, String>,
+}
+
+fn mime_type(path: &Path) -> String {
+    mime_guess::from_path(path)
+        .first_or_octet_stream()
+        .essence_str()
+        .to_string()
 }
 
 impl redb::RedbValue for CacheRecord {
@@ -68,6 +166,10 @@ impl redb::RedbValue for CacheRecord {
                 last_modified: None,
                 etag: None,
                 synchronized: false,
+                fields: HashMap::new(),
+                headers: HashMap::new(),
+                md5: None,
+                size: 0,
             },
         }
     }
@@ -126,7 +228,7 @@ impl CacheDB {
         let db = self.db.lock().unwrap();
         let read_txn = db.begin_read()?;
         let table = read_txn.open_table(TABLE)?;
-        let value = table.get(url)?.unwrap().value();
+        let value = table.get(url)?.ok_or("URL not cached")?.value();
         Ok(value)
     }
 
@@ -150,6 +252,42 @@ impl CacheDB {
         }
         Ok(())
     }
+
+    /// Return every URL the DB knows about that starts with `prefix`.
+    pub fn list(&self, prefix: &str) -> Result<Vec<(String, CacheRecord)>, Box<dyn error::Error>> {
+        let db = self.db.lock().unwrap();
+        let read_txn = db.begin_read()?;
+        let table = match read_txn.open_table(TABLE) {
+            Ok(table) => table,
+            // Nothing has been cached yet.
+            Err(redb::TableError::TableDoesNotExist(_)) => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut records = Vec::new();
+        for item in table.range::<&str>(prefix..)? {
+            let (key, value) = item?;
+            let url = key.value().to_string();
+            if !url.starts_with(prefix) {
+                break;
+            }
+            records.push((url, value.value()));
+        }
+        Ok(records)
+    }
+
+    /// Forget what the DB knows about a URL, returning the old record.
+    pub fn remove(&self, url: &str) -> Result<Option<CacheRecord>, Box<dyn error::Error>> {
+        let db = self.db.lock().unwrap();
+        let write_txn = db.begin_write()?;
+        let old_value = {
+            let mut table = write_txn.open_table(TABLE)?;
+            let old_value = table.remove(url)?;
+            old_value.map(|v| v.value())
+        };
+        write_txn.commit()?;
+        Ok(old_value)
+    }
 }
 
 impl std::fmt::Debug for CacheDB {
@@ -191,6 +329,45 @@ fn make_random_file<P: AsRef<path::Path>>(
     }
 }
 
+/// Compute the size and hex md5 digest of a file.
+fn file_md5<P: AsRef<path::Path>>(path: P) -> io::Result<(u64, String)> {
+    let mut file = fs::File::open(path)?;
+    let mut context = md5::Context::new();
+    let mut buf = [0u8; 8192];
+    let mut size = 0;
+    loop {
+        let n = io::Read::read(&mut file, &mut buf)?;
+        if n == 0 {
+            break;
+        }
+        context.consume(&buf[..n]);
+        size += n as u64;
+    }
+    Ok((size, format!("{:x}", context.compute())))
+}
+
+/// The hex md5 digest announced by the server, from `Content-MD5` or `Digest: md5=`.
+fn expected_md5(headers: &rh::HeaderMap) -> Option<String> {
+    use base64::Engine;
+    let mut encoded = header_as_string(headers, &rh::HeaderName::from_static("content-md5"));
+    if encoded.is_none() {
+        if let Some(digest) = header_as_string(headers, &rh::HeaderName::from_static("digest")) {
+            encoded = digest.split(',').find_map(|d| {
+                let (alg, value) = d.trim().split_once('=')?;
+                if alg.eq_ignore_ascii_case("md5") {
+                    Some(value.to_string())
+                } else {
+                    None
+                }
+            });
+        }
+    }
+    let decoded = base64::engine::general_purpose::STANDARD
+        .decode(encoded?.trim())
+        .ok()?;
+    Some(decoded.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
 fn header_as_string(headers: &rh::HeaderMap, key: &rh::HeaderName) -> Option<String> {
     headers.get(key).and_then(|value| match value.to_str() {
         Ok(s) => Some(s.into()),
@@ -201,6 +378,77 @@ fn header_as_string(headers: &rh::HeaderMap, key: &rh::HeaderName) -> Option<Str
     })
 }
 
+/// A download in progress, shared by everyone asking for the same URL.
+#[derive(Debug, Default)]
+struct Fetch {
+    result: Mutex<Option<String>>,
+    done: Condvar,
+}
+
+/// Coalesces concurrent downloads of the same URL.
+///
+/// The first caller runs the download,
+/// the others wait for its result instead of downloading again.
+#[derive(Debug, Default)]
+struct Fetches {
+    inflight: Mutex<HashMap<String, Arc<Fetch>>>,
+}
+
+impl Fetches {
+    /// Run `fetch` for `key` unless it is already running,
+    /// in which case wait up to `timeout` for its result.
+    ///
+    /// Returns `None` if the wait timed out.
+    fn run<F>(&self, key: &str, timeout: Duration, fetch: F) -> Option<String>
+    where
+        F: FnOnce() -> String,
+    {
+        let (current, leader) = {
+            let mut inflight = self.inflight.lock().unwrap();
+            match inflight.get(key) {
+                Some(current) => (current.clone(), false),
+                None => {
+                    let current = Arc::new(Fetch::default());
+                    inflight.insert(key.to_string(), current.clone());
+                    (current, true)
+                }
+            }
+        };
+
+        if leader {
+            // Make sure waiters are released even if the download panics.
+            struct Finish<'a> {
+                fetches: &'a Fetches,
+                key: &'a str,
+                current: Arc<Fetch>,
+                result: String,
+            }
+            impl Drop for Finish<'_> {
+                fn drop(&mut self) {
+                    self.fetches.inflight.lock().unwrap().remove(self.key);
+                    *self.current.result.lock().unwrap() = Some(self.result.clone());
+                    self.current.done.notify_all();
+                }
+            }
+            let mut finish = Finish {
+                fetches: self,
+                key,
+                current,
+                result: String::new(),
+            };
+            finish.result = fetch();
+            return Some(finish.result.clone());
+        }
+
+        let result = current.result.lock().unwrap();
+        let (result, _) = current
+            .done
+            .wait_timeout_while(result, timeout, |result| result.is_none())
+            .unwrap();
+        result.clone()
+    }
+}
+
 /// Represents a local cache of HTTP resources.
 ///
 /// Whenever you ask it for the contents of a URL,
@@ -215,7 +463,11 @@ pub struct Cache {
     root: path::PathBuf,
     db: Arc<CacheDB>,
     client: reqwest::blocking::Client,
-    file_lock: Arc<Mutex<HashMap<String, bool>>>,
+    upload: Upload,
+    fetches: Arc<Fetches>,
+    fetch_timeout: Duration,
+    /// Modification time of cached files when their checksum last matched.
+    verified: Arc<Mutex<HashMap<String, SystemTime>>>,
     event: tokio::sync::mpsc::Sender<Event>,
 }
 
@@ -223,6 +475,10 @@ impl Cache {
     /// Returns a Cache that wraps `client` and caches data in `root`.
     ///
     /// If the directory `root` does not exist, it will be created.
+    /// `headers` are sent with every request, e.g. the storage credentials,
+    /// and `upload` tells how recordings are sent back to the server.
+    /// Callers asking for a URL that is already being downloaded
+    /// wait up to `fetch_timeout` before falling back to the stale copy.
     /// If multiple instances share the same `root`
     /// (concurrently or in series),
     /// each instance will be able to re-use resources downloaded by
@@ -244,7 +500,10 @@ impl Cache {
     ///     # use std::path::PathBuf;
     ///     # fn get_my_resource() -> Result<(), Box<dyn Error>> {
     ///     let mut cache = Cache::new(
-    ///         PathBuf::from("my_cache_directory"),
+    ///         "my_cache_directory",
+    ///         reqwest::header::HeaderMap::new(),
+    ///         Upload::new(),
+    ///         Duration::from_millis(5000),
     ///     )?;
     ///     # Ok(())
     ///     # }
@@ -262,14 +521,28 @@ impl Cache {
     /// In all cases, it should be safe to blow away the entire directory
     /// and start from scratch.
     /// It's only cached data, after all.
-    pub fn new(root: &str) -> Result<Cache, Box<dyn error::Error>> {
+    pub fn new(
+        root: &str,
+        headers: rh::HeaderMap,
+        upload: Upload,
+        fetch_timeout: Duration,
+    ) -> Result<Cache, Box<dyn error::Error>> {
         let root = Path::new(root).to_path_buf();
 
         fs::DirBuilder::new().recursive(true).create(&root)?;
 
         let db = CacheDB::new(root.join("cache.db"))?;
 
+        // Downloads are kept as sent, so Content-MD5 always matches the stored bytes.
         let client = reqwest::blocking::Client::builder()
+            .default_headers(headers.clone())
+            .use_rustls_tls()
+            .no_gzip()
+            .no_brotli()
+            .no_deflate()
+            .build()?;
+        let async_client = reqwest::Client::builder()
+            .default_headers(headers)
             .use_rustls_tls()
             .build()?;
 
@@ -287,13 +560,16 @@ impl Cache {
             root,
             db: Arc::new(db),
             client,
-            file_lock: Arc::new(Mutex::new(HashMap::new())),
+            upload,
+            fetches: Arc::new(Fetches::default()),
+            fetch_timeout,
+            verified: Arc::new(Mutex::new(HashMap::new())),
             event: tx,
         };
 
         let worker = cached.clone();
         thread::spawn(move || {
-            worker.worker_thread(rx);
+            worker.worker_thread(async_client, rx);
         });
         Ok(cached)
     }
@@ -303,6 +579,8 @@ impl Cache {
             done: true,
             path: "".to_string(),
             url: "".to_string(),
+            fields: HashMap::new(),
+            headers: HashMap::new(),
         };
 
         let result = self.event.blocking_send(event);
@@ -319,6 +597,8 @@ impl Cache {
         client: reqwest::Client,
         file: &str,
         url: &str,
+        fields: HashMap<String, String>,
+        headers: HashMap<String, String>,
     ) -> Result<reqwest::Response, Box<dyn Error>> {
         let file_path = self.root.join(file);
 
@@ -337,19 +617,101 @@ impl Cache {
         // make form part of file
         let part = reqwest::multipart::Part::stream(file_body)
             .file_name(file_name.to_string())
-            .mime_str("application/octet-stream")?;
+            .mime_str(&mime_type(&file_path))?;
 
         // create the multipart form
-        let form = reqwest::multipart::Form::new().part("file", part);
+        let mut form = reqwest::multipart::Form::new();
+        for (name, value) in fields {
+            form = form.text(name, value);
+        }
+        let form = form.part(self.upload.field.clone(), part);
 
         // send request
-        let response = client.post(url).multipart(form).send().await?;
+        let mut request = client.post(url).multipart(form);
+        for (name, value) in headers {
+            request = request.header(name, value);
+        }
+        let response = request.send().await?.error_for_status()?;
         Ok(response)
     }
 
-    fn worker_thread(&self, mut rx: mpsc::Receiver<Event>) {
+    async fn reqwest_put(
+        &self,
+        client: reqwest::Client,
+        file: &str,
+        url: &str,
+        fields: HashMap<String, String>,
+        headers: HashMap<String, String>,
+    ) -> Result<reqwest::Response, Box<dyn Error>> {
+        let file_path = self.root.join(file);
+
+        let file = tokio::fs::File::open(&file_path).await?;
+        let length = file.metadata().await?.len();
+
+        // read file body stream
+        let stream = FramedRead::new(file, BytesCodec::new());
+        let file_body = reqwest::Body::wrap_stream(stream);
+
+        let mut request = client
+            .put(url)
+            .query(&fields)
+            .header(rh::CONTENT_TYPE, mime_type(&file_path))
+            .header(rh::CONTENT_LENGTH, length);
+        for (name, value) in headers {
+            request = request.header(name, value);
+        }
+        let response = request.body(file_body).send().await?.error_for_status()?;
+        Ok(response)
+    }
+
+    async fn reqwest_presigned(
+        &self,
+        client: reqwest::Client,
+        plain_client: reqwest::Client,
+        file: &str,
+        url: &str,
+        fields: HashMap<String, String>,
+        headers: HashMap<String, String>,
+    ) -> Result<reqwest::Response, Box<dyn Error>> {
+        let file_path = self.root.join(file);
+        let content_type = mime_type(&file_path);
+
+        // ask the storage server where to put the file
+        let body = serde_json::json!({
+            "url": url,
+            "content_type": &content_type,
+            "fields": fields,
+        });
+        let mut request = client.post(url).json(&body);
+        for (name, value) in headers {
+            request = request.header(name, value);
+        }
+        let presigned: Presigned = request.send().await?.error_for_status()?.json().await?;
+
+        let file = tokio::fs::File::open(&file_path).await?;
+        let length = file.metadata().await?.len();
+
+        // read file body stream
+        let stream = FramedRead::new(file, BytesCodec::new());
+        let file_body = reqwest::Body::wrap_stream(stream);
+
+        // The presigned url carries its own credentials.
+        let method = presigned.method.unwrap_or(String::from("PUT"));
+        let method = reqwest::Method::from_bytes(method.to_ascii_uppercase().as_bytes())?;
+        let mut request = plain_client
+            .request(method, &presigned.url)
+            .header(rh::CONTENT_TYPE, content_type)
+            .header(rh::CONTENT_LENGTH, length);
+        for (name, value) in presigned.headers {
+            request = request.header(name, value);
+        }
+        let response = request.body(file_body).send().await?.error_for_status()?;
+        Ok(response)
+    }
+
+    fn worker_thread(&self, client: reqwest::Client, mut rx: mpsc::Receiver<Event>) {
         let rt = tokio::runtime::Runtime::new().unwrap();
-        let client = reqwest::Client::builder().use_rustls_tls().build().unwrap();
+        let plain_client = reqwest::Client::builder().use_rustls_tls().build().unwrap();
         rt.block_on(async {
             loop {
                 let recv = rx.recv().await;
@@ -359,9 +721,42 @@ impl Cache {
                             info!("shutdown");
                             break;
                         }
-                        let response = self
-                            .reqwest_multipart_form(client.clone(), &recv.path, &recv.url)
-                            .await;
+                        crate::metrics::STORAGE_PENDING_UPLOADS.dec();
+                        let fields = recv.fields.clone();
+                        let headers = recv.headers.clone();
+                        let response = match self.upload.method {
+                            UploadMethod::Multipart => {
+                                self.reqwest_multipart_form(
+                                    client.clone(),
+                                    &recv.path,
+                                    &recv.url,
+                                    fields,
+                                    headers,
+                                )
+                                .await
+                            }
+                            UploadMethod::Put => {
+                                self.reqwest_put(
+                                    client.clone(),
+                                    &recv.path,
+                                    &recv.url,
+                                    fields,
+                                    headers,
+                                )
+                                .await
+                            }
+                            UploadMethod::Presigned => {
+                                self.reqwest_presigned(
+                                    client.clone(),
+                                    plain_client.clone(),
+                                    &recv.path,
+                                    &recv.url,
+                                    fields,
+                                    headers,
+                                )
+                                .await
+                            }
+                        };
                         match response {
                             Ok(response) => {
                                 info!("result: {:?}", response);
@@ -375,6 +770,10 @@ impl Cache {
                                         last_modified,
                                         etag,
                                         synchronized: true,
+                                        fields: recv.fields,
+                                        headers: recv.headers,
+                                        md5: None,
+                                        size: 0,
                                     },
                                 );
                             }
@@ -391,20 +790,16 @@ impl Cache {
         });
     }
 
-    fn lock_file(&self, uri: &str, lock: bool) {
-        if lock {
-            loop {
-                let lock = self.file_lock.lock().unwrap();
-                let state = lock.get(uri);
-                if state.is_none() {
-                    break;
-                }
-                drop(lock);
-                std::thread::sleep(std::time::Duration::from_millis(1000));
-            }
-            self.file_lock.lock().unwrap().insert(uri.to_string(), true);
+    /// The copy we already have of a URL, however old it is.
+    fn stale(&self, uri: &str) -> Option<String> {
+        let mut url = reqwest::Url::parse(uri).ok()?;
+        url.set_fragment(None);
+        let record = self.db.get(url.as_str()).ok()?;
+        let path = self.root.join(record.path);
+        if path.exists() {
+            Some(path.display().to_string())
         } else {
-            self.file_lock.lock().unwrap().remove(uri);
+            None
         }
     }
 
@@ -424,19 +819,70 @@ impl Cache {
             }
         }
 
-        let (mut handle, file_path) = make_random_file(&content_dir, extension)?;
-
-        // We can be sure the relative path is valid UTF-8, because
-        // make_random_file() just generated it from ASCII.
-        let path = file_path.strip_prefix(&self.root)?.to_str().unwrap().into();
+        // Download into a temporary file first, so a truncated transfer
+        // never replaces a good copy.
+        let (mut handle, tmp_path) = make_random_file(&content_dir, "part")?;
 
         let last_modified = header_as_string(response.headers(), &rh::LAST_MODIFIED);
 
         let etag = header_as_string(response.headers(), &rh::ETAG);
 
-        let count = io::copy(&mut response, &mut handle)?;
+        // Chunked responses have no length but may still carry a digest.
+        let content_length = response.content_length();
+        let content_md5 = expected_md5(response.headers());
 
-        debug!("Downloaded {} bytes", count);
+        let mut context = md5::Context::new();
+        let mut buf = [0u8; 8192];
+        let mut count: u64 = 0;
+        let copied = (|| -> io::Result<()> {
+            loop {
+                let n = io::Read::read(&mut response, &mut buf)?;
+                if n == 0 {
+                    break;
+                }
+                context.consume(&buf[..n]);
+                io::Write::write_all(&mut handle, &buf[..n])?;
+                count += n as u64;
+            }
+            handle.sync_all()
+        })();
+        drop(handle);
+        let md5 = format!("{:x}", context.compute());
+
+        let checked = match copied {
+            Err(e) => Err(e.to_string()),
+            Ok(()) => match (content_length, content_md5) {
+                (Some(length), _) if length != count => Err(format!(
+                    "truncated download, got {} of {} bytes",
+                    count, length
+                )),
+                (_, Some(expected)) if expected != md5 => Err(format!(
+                    "checksum mismatch, got {} expected {}",
+                    md5, expected
+                )),
+                _ => Ok(()),
+            },
+        };
+        if let Err(e) = checked {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(format!("Download {} failed, {}", url, e).into());
+        }
+
+        debug!("Downloaded {} bytes, md5 {}", count, md5);
+        crate::metrics::STORAGE_MISSES.inc();
+        crate::metrics::STORAGE_DOWNLOAD_BYTES.add(count);
+
+        let (handle, file_path) = make_random_file(&content_dir, extension)?;
+        drop(handle);
+        if let Err(e) = fs::rename(&tmp_path, &file_path) {
+            let _ = fs::remove_file(&tmp_path);
+            let _ = fs::remove_file(&file_path);
+            return Err(e.into());
+        }
+
+        // We can be sure the relative path is valid UTF-8, because
+        // make_random_file() just generated it from ASCII.
+        let path = file_path.strip_prefix(&self.root)?.to_str().unwrap().into();
 
         self.db.set(
             url,
@@ -445,29 +891,74 @@ impl Cache {
                 last_modified,
                 etag,
                 synchronized: true,
+                fields: HashMap::new(),
+                headers: HashMap::new(),
+                md5: Some(md5),
+                size: count,
             },
         )?;
 
         Ok(file_path)
     }
 
+    /// Check a cached file against the size and checksum recorded when it was downloaded.
+    /// The checksum is computed again only when the file was modified since it last matched.
+    fn verify(&self, record: &CacheRecord) -> bool {
+        let expected = match &record.md5 {
+            Some(md5) => md5,
+            // Nothing to check against, e.g. a local recording.
+            None => return true,
+        };
+        let path = self.root.join(&record.path);
+        let modified = match fs::metadata(&path) {
+            Ok(metadata) if metadata.len() == record.size => metadata.modified().ok(),
+            _ => return false,
+        };
+        let mut verified = self.verified.lock().unwrap();
+        if modified.is_some() && verified.get(&record.path) == modified.as_ref() {
+            return true;
+        }
+        match file_md5(&path) {
+            Ok((_, md5)) if md5.eq(expected) => {
+                if let Some(modified) = modified {
+                    verified.insert(record.path.clone(), modified);
+                }
+                true
+            }
+            _ => {
+                verified.remove(&record.path);
+                false
+            }
+        }
+    }
+
     fn load_cache(&self, mut url: reqwest::Url) -> Result<PathBuf, Box<dyn error::Error>> {
         use reqwest::StatusCode;
 
         url.set_fragment(None);
 
-        let response = match self.db.get(url.as_str()) {
+        let cached = match self.db.get(url.as_str()) {
+            Ok(record) if record.synchronized && !self.verify(&record) => {
+                warn!("Cached file {} of {} is corrupt, download again", record.path, url);
+                Err("corrupt cache file".into())
+            }
+            cached => cached,
+        };
+
+        let response = match cached {
             Ok(CacheRecord {
                 path: p,
                 last_modified: lm,
                 etag: et,
                 synchronized: sync,
+                ..
             }) => {
                 // We have a locally-cached copy
 
                 // The file is not synchronized, using the local cache
                 if !sync {
                     debug!("Unsynchronized caches, using the local cache");
+                    crate::metrics::STORAGE_HITS.inc();
                     return Ok(self.root.join(p));
                 }
 
@@ -503,6 +994,7 @@ impl Cache {
                         if new_response.status() == StatusCode::NOT_MODIFIED {
                             // ... let's use it as is.
                             debug!("Hit cache, using the local cache data");
+                            crate::metrics::STORAGE_HITS.inc();
                             return Ok(self.root.join(p));
                         }
 
@@ -513,6 +1005,7 @@ impl Cache {
                     Err(e) => {
                         warn!("Could not validate cached response: {}", e);
                         // Let's just use the existing data we have.
+                        crate::metrics::STORAGE_HITS.inc();
                         return Ok(self.root.join(p));
                     }
                 }
@@ -581,8 +1074,24 @@ impl Cache {
     /// so you might want to destroy this `Cache` instance
     /// and create a new one pointing at the same location.
     pub fn get(&self, uri: &str) -> String {
+        let result = self
+            .fetches
+            .run(uri, self.fetch_timeout, || self.fetch(uri));
+        match result {
+            Some(cache_file) if !cache_file.is_empty() => cache_file,
+            Some(_) => {
+                warn!("Fetching {} failed, using the stale copy", uri);
+                self.stale(uri).unwrap_or_default()
+            }
+            None => {
+                warn!("Timed out waiting for {}, using the stale copy", uri);
+                self.stale(uri).unwrap_or_default()
+            }
+        }
+    }
+
+    fn fetch(&self, uri: &str) -> String {
         let mut cache_file = String::new();
-        self.lock_file(uri, true);
         let url = reqwest::Url::parse(uri);
         match url {
             Ok(url) => {
@@ -600,7 +1109,6 @@ impl Cache {
                 error!("Bad Url: {}, {}", uri, e);
             }
         }
-        self.lock_file(uri, false);
         cache_file
     }
 
@@ -631,7 +1139,15 @@ impl Cache {
         }
     }
 
-    pub fn close_cached_file(&self, url: &str, path: &str) -> Result<(), Box<dyn Error>> {
+    /// Record a finished recording and queue it for upload,
+    /// `fields` and `headers` are sent along with the file.
+    pub fn close_cached_file(
+        &self,
+        url: &str,
+        path: &str,
+        fields: HashMap<String, String>,
+        headers: HashMap<String, String>,
+    ) -> Result<(), Box<dyn Error>> {
         self.db.set(
             url,
             CacheRecord {
@@ -639,6 +1155,10 @@ impl Cache {
                 last_modified: None,
                 etag: None,
                 synchronized: false,
+                fields: fields.clone(),
+                headers: headers.clone(),
+                md5: None,
+                size: 0,
             },
         )?;
 
@@ -646,9 +1166,193 @@ impl Cache {
             done: false,
             path: path.to_string(),
             url: url.to_string(),
+            fields,
+            headers,
         };
 
-        self.event.blocking_send(ev)?;
+        crate::metrics::STORAGE_PENDING_UPLOADS.inc();
+        if let Err(e) = self.event.blocking_send(ev) {
+            crate::metrics::STORAGE_PENDING_UPLOADS.dec();
+            return Err(e.into());
+        }
         Ok(())
     }
+
+    /// List the cached URLs that start with `prefix`.
+    pub fn list(&self, prefix: &str) -> Result<Vec<CacheEntry>, Box<dyn Error>> {
+        let records = self.db.list(prefix)?;
+        let entries = records
+            .into_iter()
+            .map(|(url, record)| {
+                let size = fs::metadata(self.root.join(&record.path))
+                    .map(|m| m.len())
+                    .unwrap_or(0);
+                CacheEntry {
+                    url,
+                    path: record.path,
+                    size,
+                    last_modified: record.last_modified,
+                    etag: record.etag,
+                    synchronized: record.synchronized,
+                    md5: record.md5,
+                }
+            })
+            .collect();
+        Ok(entries)
+    }
+
+    /// Remove `url` from the cache, or every URL starting with it if `prefix` is set.
+    ///
+    /// Recordings that have not been uploaded yet are kept.
+    /// Returns the number of URLs removed.
+    pub fn purge(&self, url: &str, prefix: bool) -> Result<usize, Box<dyn Error>> {
+        let mut count = 0;
+        for (key, record) in self.db.list(url)? {
+            if !prefix && key != url {
+                continue;
+            }
+            if !record.synchronized {
+                warn!("Skip purging unsynchronized file {}", key);
+                continue;
+            }
+            if let Some(record) = self.db.remove(&key)? {
+                let _ = fs::remove_file(self.root.join(record.path));
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    /// Download `urls` ahead of time, returning the ones that could not be fetched.
+    pub fn prefetch(&self, urls: &[String]) -> Vec<String> {
+        let mut failed = Vec::new();
+        for url in urls {
+            if self.get(url).is_empty() {
+                failed.push(url.clone());
+            }
+        }
+        failed
+    }
+
+    /// Queue every unsynchronized recording for upload again.
+    ///
+    /// Returns the number of recordings queued.
+    pub fn resync(&self) -> Result<usize, Box<dyn Error>> {
+        let mut count = 0;
+        for (url, record) in self.db.list("")? {
+            if record.synchronized {
+                continue;
+            }
+            let ev = Event {
+                done: false,
+                path: record.path,
+                url,
+                fields: record.fields,
+                headers: record.headers,
+            };
+            crate::metrics::STORAGE_PENDING_UPLOADS.inc();
+            if let Err(e) = self.event.blocking_send(ev) {
+                crate::metrics::STORAGE_PENDING_UPLOADS.dec();
+                return Err(e.into());
+            }
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Barrier;
+    use std::time::Instant;
+
+    #[test]
+    fn concurrent_fetches_are_coalesced() {
+        let fetches = Arc::new(Fetches::default());
+        let count = Arc::new(AtomicUsize::new(0));
+        let barrier = Arc::new(Barrier::new(8));
+
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let fetches = fetches.clone();
+                let count = count.clone();
+                let barrier = barrier.clone();
+                thread::spawn(move || {
+                    barrier.wait();
+                    fetches.run("http://example.com/a.wav", Duration::from_secs(5), || {
+                        count.fetch_add(1, Ordering::SeqCst);
+                        thread::sleep(Duration::from_millis(200));
+                        String::from("download/a.wav")
+                    })
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            assert_eq!(handle.join().unwrap().as_deref(), Some("download/a.wav"));
+        }
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+        assert!(fetches.inflight.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn waiters_are_woken_when_the_fetch_finishes() {
+        let fetches = Arc::new(Fetches::default());
+        let leader = {
+            let fetches = fetches.clone();
+            thread::spawn(move || {
+                fetches.run("http://example.com/a.wav", Duration::from_secs(5), || {
+                    thread::sleep(Duration::from_millis(100));
+                    String::from("download/a.wav")
+                })
+            })
+        };
+        thread::sleep(Duration::from_millis(20));
+
+        let start = Instant::now();
+        let result = fetches.run("http://example.com/a.wav", Duration::from_secs(5), || {
+            String::from("download/b.wav")
+        });
+        assert_eq!(result.as_deref(), Some("download/a.wav"));
+        assert!(start.elapsed() < Duration::from_secs(1));
+        leader.join().unwrap();
+    }
+
+    #[test]
+    fn waiters_give_up_after_the_timeout() {
+        let fetches = Arc::new(Fetches::default());
+        let leader = {
+            let fetches = fetches.clone();
+            thread::spawn(move || {
+                fetches.run("http://example.com/a.wav", Duration::from_secs(5), || {
+                    thread::sleep(Duration::from_millis(500));
+                    String::from("download/a.wav")
+                })
+            })
+        };
+        thread::sleep(Duration::from_millis(20));
+
+        let start = Instant::now();
+        let result = fetches.run("http://example.com/a.wav", Duration::from_millis(50), || {
+            String::from("download/b.wav")
+        });
+        assert_eq!(result, None);
+        assert!(start.elapsed() < Duration::from_millis(400));
+        leader.join().unwrap();
+    }
+
+    #[test]
+    fn different_urls_are_fetched_independently() {
+        let fetches = Fetches::default();
+        let a = fetches.run("http://example.com/a.wav", Duration::from_secs(1), || {
+            String::from("download/a.wav")
+        });
+        let b = fetches.run("http://example.com/b.wav", Duration::from_secs(1), || {
+            String::from("download/b.wav")
+        });
+        assert_eq!(a.as_deref(), Some("download/a.wav"));
+        assert_eq!(b.as_deref(), Some("download/b.wav"));
+    }
 }
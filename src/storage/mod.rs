@@ -1,4 +1,4 @@
-use std::{ffi::CString, sync::Mutex};
+use std::{collections::{HashMap, HashSet}, ffi::CString, sync::Mutex};
 
 use switch_sys::*;
 use lazy_static::lazy_static;
@@ -6,6 +6,8 @@ use libc::c_char;
 
 mod cache;
 
+pub use cache::CacheEntry;
+
 #[derive(Debug, Clone)]
 pub struct Profile {
     pub name: String,
@@ -13,10 +15,24 @@ pub struct Profile {
     pub file_not_found_expires: i32,
     /// how often to re-check the server to make sure the remote file has not changed
     pub file_cache_ttl: i32,
+    /// how long to wait for a file another call is downloading, in milliseconds
+    pub fetch_timeout: u64,
     /// storage server url
     pub url: String,
     /// cache temp files path
     pub cache_dir: String,
+    /// http basic auth user name
+    pub username: String,
+    /// http basic auth password
+    pub password: String,
+    /// http bearer token, used instead of basic auth when set
+    pub token: String,
+    /// extra form fields sent with recordings, values may use ${variables}
+    pub upload_fields: Vec<(String, String)>,
+    /// extra headers sent with recordings, values may use ${variables}
+    pub upload_headers: Vec<(String, String)>,
+    /// how recordings are uploaded
+    upload: cache::Upload,
     /// http cache
     cached: Option<cache::Cache>,
 }
@@ -28,9 +44,42 @@ impl Profile {
             name: "".to_string(),
             file_not_found_expires: 1,
             file_cache_ttl: 1,
+            fetch_timeout: 5000,
             url: "".to_string(),
             cache_dir: "".to_string(),
+            username: "".to_string(),
+            password: "".to_string(),
+            token: "".to_string(),
+            upload_fields: Vec::new(),
+            upload_headers: Vec::new(),
+            upload: cache::Upload::new(),
+        }
+    }
+
+    /// Default headers sent with every request to the storage server.
+    fn headers(&self) -> reqwest::header::HeaderMap {
+        use base64::Engine;
+        use reqwest::header;
+        let mut headers = header::HeaderMap::new();
+        let mut authorization = String::new();
+        if !self.token.is_empty() {
+            authorization = format!("Bearer {}", self.token);
+        } else if !self.username.is_empty() {
+            let credentials = format!("{}:{}", self.username, self.password);
+            authorization = format!("Basic {}", base64::engine::general_purpose::STANDARD.encode(credentials));
+        }
+        if !authorization.is_empty() {
+            match header::HeaderValue::from_str(&authorization) {
+                Ok(mut value) => {
+                    value.set_sensitive(true);
+                    headers.insert(header::AUTHORIZATION, value);
+                }
+                Err(e) => {
+                    error!("Invalid credentials for storage [{}], {}", self.name, e);
+                }
+            }
         }
+        headers
     }
 }
 
@@ -55,6 +104,8 @@ impl Global {
 
 lazy_static! {
     static ref GOLOBAS: Mutex<Global> = Mutex::new(Global::new());
+    /// `${name}` in upload field and header templates
+    static ref TEMPLATE_VARIABLE: regex::Regex = regex::Regex::new(r"\$\{([^}]+)\}").unwrap();
 }
 
 #[derive(Debug, Clone)]
@@ -64,6 +115,8 @@ struct FileContext {
     pub stream: String,
     pub cache_file: String,
     pub samples: u32,
+    /// variables passed as {name=value} with the file path
+    pub vars: HashMap<String, String>,
     fh: switch_file_handle_t,
     cached: Option<cache::Cache>,
 }
@@ -76,6 +129,7 @@ impl FileContext {
             file_path: "".to_string(),
             cache_file: "".to_string(),
             samples: 10,
+            vars: HashMap::new(),
             fh: Default::default(),
             cached: None,
         }
@@ -95,13 +149,26 @@ pub fn load_config(cfg: switch_xml_t) {
             return;
         }
 
+        // Built aside and swapped in at the end, so a reload replaces the profiles
+        let mut profiles: Vec<Profile> = Vec::new();
+        let mut names = HashSet::new();
         let tmp_str = CString::new("storage").unwrap();
         let mut storage_tag = switch_sys::switch_xml_child(storages_tag, tmp_str.as_ptr());
         while !storage_tag.is_null() {
             let tmp_str = CString::new("name").unwrap();
             let bname = switch_xml_attr_soft(storage_tag, tmp_str.as_ptr());
             let name = switch_to_string(bname);
-            if !name.eq_ignore_ascii_case("hfs") && !name.eq_ignore_ascii_case("s3") {
+            // The name is registered as the file interface extension, e.g. prompts://
+            let valid = name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+            if name.is_empty() || !valid {
+                warn!("Invalid storage name [{}], skipped", name);
+                storage_tag = (*storage_tag).next;
+                continue;
+            }
+            if !names.insert(name.to_ascii_lowercase()) {
+                warn!("Duplicate storage name [{}], skipped", name);
                 storage_tag = (*storage_tag).next;
                 continue;
             }
@@ -136,10 +203,37 @@ pub fn load_config(cfg: switch_xml_t) {
                     if profile.file_cache_ttl > 120 {
                         profile.file_cache_ttl = 120;
                     }
+                } else if var.eq_ignore_ascii_case("fetch-timeout") {
+                    profile.fetch_timeout = val.parse::<u64>().unwrap_or(5000).clamp(100, 60000);
                 } else if var.eq_ignore_ascii_case("cache-dir") {
                     if !val.is_empty() {
                         profile.cache_dir = val;
                     }
+                } else if var.eq_ignore_ascii_case("username") {
+                    profile.username = val;
+                } else if var.eq_ignore_ascii_case("password") {
+                    profile.password = val;
+                } else if var.eq_ignore_ascii_case("token") {
+                    profile.token = val;
+                } else if var.eq_ignore_ascii_case("upload-method") {
+                    match cache::UploadMethod::from_str(&val) {
+                        Some(method) => profile.upload.method = method,
+                        None => {
+                            warn!("Invalid upload-method [{}], using multipart", val);
+                        }
+                    }
+                } else if var.eq_ignore_ascii_case("upload-field") {
+                    if !val.is_empty() {
+                        profile.upload.field = val;
+                    }
+                } else if var.eq_ignore_ascii_case("upload-form-field") {
+                    if let Some((name, value)) = val.split_once('=') {
+                        profile.upload_fields.push((name.trim().to_string(), value.to_string()));
+                    }
+                } else if var.eq_ignore_ascii_case("upload-header") {
+                    if let Some((name, value)) = val.split_once('=') {
+                        profile.upload_headers.push((name.trim().to_string(), value.to_string()));
+                    }
                 }
 
                 if profile.cache_dir.is_empty() {
@@ -153,11 +247,16 @@ pub fn load_config(cfg: switch_xml_t) {
             }
 
             if profile.url.starts_with("http://") || profile.url.starts_with("https://") {
-                let cached = cache::Cache::new(&profile.cache_dir);
+                let cached = cache::Cache::new(
+                    &profile.cache_dir,
+                    profile.headers(),
+                    profile.upload.clone(),
+                    std::time::Duration::from_millis(profile.fetch_timeout),
+                );
                 match cached {
                     Ok(cached) => {
                         profile.cached = Some(cached);
-                        GOLOBAS.lock().unwrap().profiles.push(profile);
+                        profiles.push(profile);
                     }
                     Err(e) => {
                         error!("Failed to create cache {}", e);
@@ -166,7 +265,44 @@ pub fn load_config(cfg: switch_xml_t) {
             }
             storage_tag = (*storage_tag).next;
         }
+
+        let replaced = std::mem::replace(&mut GOLOBAS.lock().unwrap().profiles, profiles);
+        for profile in replaced {
+            if let Some(cached) = profile.cached {
+                cached.close();
+            }
+        }
+    }
+}
+
+/// Expand ${name} in `template`, looking in the recording variables first
+/// and then in the channel variables of the recording session.
+fn expand_template(template: &str, vars: &HashMap<String, String>) -> String {
+    let uuid = vars.get("uuid").cloned().unwrap_or_default();
+    TEMPLATE_VARIABLE.replace_all(template, |caps: &regex::Captures| {
+        let name = &caps[1];
+        match vars.get(name) {
+            Some(value) => value.clone(),
+            None => switch_sys::get_channel_variable(&uuid, name).unwrap_or_default(),
+        }
+    })
+    .to_string()
+}
+
+/// The extra form fields and headers of an upload, see `expand_template`.
+fn upload_extras(
+    profile: &Profile,
+    vars: &HashMap<String, String>,
+) -> (HashMap<String, String>, HashMap<String, String>) {
+    let mut fields = HashMap::new();
+    let mut headers = HashMap::new();
+    for (name, template) in &profile.upload_fields {
+        fields.insert(name.clone(), expand_template(template, vars));
+    }
+    for (name, template) in &profile.upload_headers {
+        headers.insert(name.clone(), expand_template(template, vars));
     }
+    (fields, headers)
 }
 
 unsafe extern "C" fn vfs_file_open(
@@ -193,6 +329,9 @@ unsafe extern "C" fn vfs_file_open(
                 (*fh).samples = (*handle).samples;
                 (*fh).samplerate = (*handle).samplerate;
                 (*fh).prefix = (*handle).prefix;
+                if !(*handle).params.is_null() {
+                    context.vars = switch_sys::Event::from_ptr((*handle).params).headers;
+                }
             } else {
                 if let Some(cached) = &profile.cached {
                     context.cache_file = cached.get(&context.file_url);
@@ -254,13 +393,24 @@ unsafe extern "C" fn vfs_file_open(
 unsafe extern "C" fn vfs_file_close(handle: *mut switch_file_handle_t) -> switch_status_t {
     let mut context = Box::from_raw((*handle).private_info as *mut FileContext);
     let fh = context.file_ptr();
+    let duration = (*fh).samples_out as u64 / (*fh).samplerate.max(1) as u64;
     if ((*fh).flags & switch_file_flag_enum_t::SWITCH_FILE_OPEN.0) != 0 {
         switch_core_file_close(fh);
     }
 
     if ((*handle).flags & switch_file_flag_enum_t::SWITCH_FILE_FLAG_WRITE.0) != 0 {
         if let Some(cached) = &context.cached {
-            let resonse = cached.close_cached_file(&context.file_url, &context.cache_file);
+            let mut vars = context.vars.clone();
+            vars.insert("duration".to_string(), duration.to_string());
+            vars.insert("file_path".to_string(), context.file_path.clone());
+
+            let (fields, headers) = match Global::get(&context.stream) {
+                Some(profile) => upload_extras(&profile, &vars),
+                None => (HashMap::new(), HashMap::new()),
+            };
+
+            let resonse =
+                cached.close_cached_file(&context.file_url, &context.cache_file, fields, headers);
             match resonse {
                 Ok(()) => (),
                 Err(e) => {
@@ -340,6 +490,129 @@ unsafe extern "C" fn vfs_file_write_video(
     switch_core_file_write_video((*context).file_ptr(), frame)
 }
 
+/// Expand a path relative to the profile url, full urls must be under it
+/// since the storage credentials are sent along.
+fn profile_url(profile: &Profile, path: &str) -> Result<String, String> {
+    if !path.starts_with("http://") && !path.starts_with("https://") {
+        return Ok(format!("{}/{}", profile.url, path));
+    }
+    let base = profile.url.trim_end_matches('/');
+    match path.strip_prefix(base) {
+        Some(rest) if !base.is_empty() && (rest.is_empty() || rest.starts_with('/')) => {
+            Ok(path.to_string())
+        }
+        _ => Err(format!(
+            "-ERR {} is not under the url of storage profile [{}]",
+            path, profile.name
+        )),
+    }
+}
+
+fn profile_cache(name: &str) -> Result<(Profile, cache::Cache), String> {
+    let profile = Global::get(name).ok_or(format!("-ERR No such storage profile [{}]", name))?;
+    let cached = profile
+        .cached
+        .clone()
+        .ok_or(format!("-ERR Storage profile [{}] has no cache", name))?;
+    Ok((profile, cached))
+}
+
+/// List the cached files of a storage profile, optionally limited to a path prefix.
+pub fn cache_list(name: &str, prefix: &str) -> Result<Vec<CacheEntry>, String> {
+    let (profile, cached) = profile_cache(name)?;
+    let mut prefix = prefix.to_string();
+    if !prefix.is_empty() {
+        prefix = profile_url(&profile, &prefix)?;
+    }
+    cached.list(&prefix).map_err(|e| format!("-ERR {}", e))
+}
+
+/// Purge one file, or every file under a path prefix, from a storage profile cache.
+pub fn cache_purge(name: &str, url: &str, prefix: bool) -> Result<usize, String> {
+    let (profile, cached) = profile_cache(name)?;
+    if url.is_empty() && !prefix {
+        return Err(String::from("-ERR Missing url"));
+    }
+    let mut url = url.to_string();
+    if !url.is_empty() {
+        url = profile_url(&profile, &url)?;
+    }
+    cached.purge(&url, prefix).map_err(|e| format!("-ERR {}", e))
+}
+
+/// Download files into a storage profile cache, returning the ones that failed.
+pub fn cache_prefetch(name: &str, urls: &[String]) -> Result<Vec<String>, String> {
+    let (profile, cached) = profile_cache(name)?;
+    let urls = urls
+        .iter()
+        .filter(|url| !url.is_empty())
+        .map(|url| profile_url(&profile, url))
+        .collect::<Result<Vec<String>, String>>()?;
+    Ok(cached.prefetch(&urls))
+}
+
+/// Upload again the recordings of a storage profile that are not synchronized.
+pub fn cache_resync(name: &str) -> Result<usize, String> {
+    let (_, cached) = profile_cache(name)?;
+    cached.resync().map_err(|e| format!("-ERR {}", e))
+}
+
+/// Put the local `file` into a storage profile cache as `path` and queue its upload,
+/// returning what to play it as, e.g. `prompts://welcome.wav`.
+pub fn cache_store(name: &str, path: &str, file: &std::path::Path) -> Result<String, String> {
+    let (profile, cached) = profile_cache(name)?;
+    let url = profile_url(&profile, path)?;
+    let cache_file = cached.create_cached_file(path);
+    if std::fs::rename(file, &cache_file).is_err() {
+        std::fs::copy(file, &cache_file).map_err(|e| format!("-ERR {}", e))?;
+    }
+    let vars = HashMap::from([(String::from("file_path"), path.to_string())]);
+    let (fields, headers) = upload_extras(&profile, &vars);
+    if let Err(e) = cached.close_cached_file(&url, &cache_file, fields, headers) {
+        let _ = std::fs::remove_file(&cache_file);
+        return Err(format!("-ERR {}", e));
+    }
+    Ok(format!("{}://{}", profile.name, path))
+}
+
+/// `rustit storage` api command
+pub fn api_command(args: &[&str]) -> Result<String, String> {
+    let usage = String::from(
+        "-ERR Usage: storage <list|purge|prefetch|resync> <profile> [url[*]|url,url...]",
+    );
+    if args.len() < 2 {
+        return Err(usage);
+    }
+    let cmd = args[0];
+    let name = args[1];
+    let arg = args.get(2).copied().unwrap_or_default();
+
+    if cmd.eq_ignore_ascii_case("list") {
+        let entries = cache_list(name, arg)?;
+        serde_json::to_string_pretty(&entries).map_err(|e| format!("-ERR {}", e))
+    } else if cmd.eq_ignore_ascii_case("purge") {
+        let (url, prefix) = match arg.strip_suffix('*') {
+            Some(url) => (url, true),
+            None => (arg, false),
+        };
+        let count = cache_purge(name, url, prefix)?;
+        Ok(format!("+OK {} purged", count))
+    } else if cmd.eq_ignore_ascii_case("prefetch") {
+        let urls: Vec<String> = arg.split(',').map(|url| url.trim().to_string()).collect();
+        let failed = cache_prefetch(name, &urls)?;
+        if failed.is_empty() {
+            Ok(String::from("+OK"))
+        } else {
+            Err(format!("-ERR Failed to fetch {}", failed.join(",")))
+        }
+    } else if cmd.eq_ignore_ascii_case("resync") {
+        let count = cache_resync(name)?;
+        Ok(format!("+OK {} queued", count))
+    } else {
+        Err(usage)
+    }
+}
+
 pub fn shutdown() {
     loop {
         let profile = GOLOBAS.lock().unwrap().profiles.pop();
@@ -389,3 +662,28 @@ pub fn start(m: &switch_sys::Module, name: &str) {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profile_url_stays_under_the_profile() {
+        let mut profile = Profile::new();
+        profile.name = String::from("hfs");
+        profile.url = String::from("https://files.example.com/storage");
+
+        assert_eq!(
+            profile_url(&profile, "a/b.wav").unwrap(),
+            "https://files.example.com/storage/a/b.wav"
+        );
+        assert_eq!(
+            profile_url(&profile, "https://files.example.com/storage/a.wav").unwrap(),
+            "https://files.example.com/storage/a.wav"
+        );
+        assert!(profile_url(&profile, "https://evil.example.com/a.wav").is_err());
+        assert!(profile_url(&profile, "http://files.example.com/storage/a.wav").is_err());
+        assert!(profile_url(&profile, "https://files.example.com/storage.evil.com/a.wav").is_err());
+        assert!(profile_url(&profile, "https://files.example.com/other/a.wav").is_err());
+    }
+}
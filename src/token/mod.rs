@@ -0,0 +1,188 @@
+use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+
+/// What a signed token carries, an HS256 JWT.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Claims {
+    /// Who it was minted for
+    pub sub: String,
+    /// Which interface accepts it, e.g. `api`
+    pub aud: String,
+    /// Expiry, seconds since the epoch
+    pub exp: u64,
+    /// Scope of the client, `command` or `events`
+    #[serde(default)]
+    pub scope: String,
+    /// Command policy of the client
+    #[serde(default)]
+    pub policy: String,
+    /// gRPC methods the client may call, `*` is all, none when empty
+    #[serde(default)]
+    pub rpcs: Vec<String>,
+}
+
+impl Claims {
+    /// Claims for `sub` valid for `ttl` seconds from now.
+    pub fn new(sub: &str, aud: &str, ttl: u64) -> Claims {
+        Claims {
+            sub: sub.to_string(),
+            aud: aud.to_string(),
+            exp: jsonwebtoken::get_current_timestamp() + ttl,
+            scope: String::new(),
+            policy: String::new(),
+            rpcs: Vec::new(),
+        }
+    }
+}
+
+/// Compare a presented secret in constant time, so timing doesn't tell
+/// how much of it was right.
+pub fn secret_eq(presented: &str, secret: &str) -> bool {
+    use subtle::ConstantTimeEq;
+    presented.as_bytes().ct_eq(secret.as_bytes()).into()
+}
+
+/// A secret for when none is configured, tokens then die with the module.
+pub fn random_secret() -> String {
+    thread_rng()
+        .sample_iter(Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect()
+}
+
+pub fn mint(secret: &str, claims: &Claims) -> Result<String, String> {
+    encode(
+        &Header::new(Algorithm::HS256),
+        claims,
+        &EncodingKey::from_secret(secret.as_bytes()),
+    )
+    .map_err(|e| format!("-ERR {}", e))
+}
+
+/// Check the signature, expiry and audience of `token`.
+pub fn verify(secret: &str, token: &str, aud: &str) -> Result<Claims, String> {
+    Verifier::hmac(secret).verify(token, aud)
+}
+
+/// Checks tokens signed with a shared secret (HS256) or an RSA key (RS256).
+#[derive(Clone)]
+pub struct Verifier {
+    key: DecodingKey,
+    algorithm: Algorithm,
+}
+
+impl Verifier {
+    pub fn hmac(secret: &str) -> Verifier {
+        Verifier {
+            key: DecodingKey::from_secret(secret.as_bytes()),
+            algorithm: Algorithm::HS256,
+        }
+    }
+
+    /// From the PEM encoded public key of the signer.
+    pub fn rsa_pem(pem: &[u8]) -> Result<Verifier, String> {
+        let key = DecodingKey::from_rsa_pem(pem).map_err(|e| format!("-ERR {}", e))?;
+        Ok(Verifier {
+            key,
+            algorithm: Algorithm::RS256,
+        })
+    }
+
+    pub fn verify(&self, token: &str, aud: &str) -> Result<Claims, String> {
+        let mut validation = Validation::new(self.algorithm);
+        validation.leeway = 0;
+        validation.set_audience(&[aud]);
+        decode::<Claims>(token, &self.key, &validation)
+            .map(|data| data.claims)
+            .map_err(|e| format!("-ERR Invalid token, {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "test-secret";
+
+    #[test]
+    fn valid_token_round_trips() {
+        let mut claims = Claims::new("alice", "grpc", 60);
+        claims.policy = String::from("remote");
+        claims.rpcs = vec![String::from("Subscribe"), String::from("Command")];
+        let token = mint(SECRET, &claims).unwrap();
+        let verified = verify(SECRET, &token, "grpc").unwrap();
+        assert_eq!(verified.sub, "alice");
+        assert_eq!(verified.policy, "remote");
+        assert_eq!(verified.rpcs, claims.rpcs);
+    }
+
+    #[test]
+    fn secrets_compare_exactly() {
+        assert!(secret_eq("change-me", "change-me"));
+        assert!(!secret_eq("change-m", "change-me"));
+        assert!(!secret_eq("change-me!", "change-me"));
+        assert!(!secret_eq("", "change-me"));
+    }
+
+    #[test]
+    fn expired_token_is_refused() {
+        let mut claims = Claims::new("alice", "grpc", 0);
+        claims.exp -= 10;
+        let token = mint(SECRET, &claims).unwrap();
+        assert!(verify(SECRET, &token, "grpc").is_err());
+    }
+
+    #[test]
+    fn wrong_audience_or_secret_is_refused() {
+        let token = mint(SECRET, &Claims::new("alice", "api", 60)).unwrap();
+        assert!(verify(SECRET, &token, "grpc").is_err());
+        assert!(verify("other-secret", &token, "api").is_err());
+    }
+
+    #[test]
+    fn wrong_algorithm_is_refused() {
+        let claims = Claims::new("alice", "grpc", 60);
+        let token = encode(
+            &Header::new(Algorithm::HS384),
+            &claims,
+            &EncodingKey::from_secret(SECRET.as_bytes()),
+        )
+        .unwrap();
+        assert!(verify(SECRET, &token, "grpc").is_err());
+
+        // An HS256 token signed with the bytes of a public key is no RS256 token
+        let hs256 = mint(SECRET, &claims).unwrap();
+        let verifier = Verifier {
+            key: DecodingKey::from_secret(SECRET.as_bytes()),
+            algorithm: Algorithm::RS256,
+        };
+        assert!(verifier.verify(&hs256, "grpc").is_err());
+    }
+
+    #[test]
+    fn tokens_without_rpcs_claim_decode() {
+        #[derive(Serialize)]
+        struct Old {
+            sub: String,
+            aud: String,
+            exp: u64,
+        }
+        let old = Old {
+            sub: String::from("alice"),
+            aud: String::from("grpc"),
+            exp: jsonwebtoken::get_current_timestamp() + 60,
+        };
+        let token = encode(
+            &Header::new(Algorithm::HS256),
+            &old,
+            &EncodingKey::from_secret(SECRET.as_bytes()),
+        )
+        .unwrap();
+        let claims = verify(SECRET, &token, "grpc").unwrap();
+        assert!(claims.rpcs.is_empty());
+        assert!(claims.policy.is_empty());
+    }
+}
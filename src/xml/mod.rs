@@ -1,6 +1,7 @@
 use switch_sys::*;
 use lazy_static::lazy_static;
 use std::{ffi::CString, sync::RwLock};
+use std::time::Instant;
 use tokio::time::Duration;
 mod preprocess;
 
@@ -82,6 +83,7 @@ fn xml_fetch(data: String) -> String {
             request = data.clone();
         }
         let client = binding.client.clone();
+        let started = Instant::now();
         let response = client
             .post(&binding.url)
             .timeout(Duration::from_millis(binding.timeout))
@@ -90,6 +92,7 @@ fn xml_fetch(data: String) -> String {
         match response {
             Ok(response) => {
                 let body = response.text();
+                crate::metrics::xml_fetch(&binding.name).observe(started.elapsed());
                 match body {
                     Ok(body) => {
                         let text = preprocess::expand_vars(&body);
@@ -108,6 +111,7 @@ fn xml_fetch(data: String) -> String {
                 }
             }
             Err(e) => {
+                crate::metrics::xml_fetch(&binding.name).observe(started.elapsed());
                 error!("{}", e);
             }
         }
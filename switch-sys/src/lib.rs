@@ -45,9 +45,16 @@ pub fn check_acl(ip: &str, list: &str) -> bool {
     }
 }
 
+/// A C string of `s`, or an error naming `what` when it holds a NUL byte.
+fn to_cstring(s: &str, what: &str) -> Result<CString, String> {
+    CString::new(s).map_err(|_| format!("-ERR invalid {}", what))
+}
+
 /// api_exec
 /// execute FreeSWITCH api
 pub fn api_exec(cmd: &str, arg: &str) -> Result<String, String> {
+    let api_cmd = to_cstring(cmd, "command")?;
+    let api_arg = to_cstring(arg, "command arguments")?;
     unsafe {
         let data_size: usize = 1024;
         let data = libc::malloc(data_size);
@@ -65,8 +72,6 @@ pub fn api_exec(cmd: &str, arg: &str) -> Result<String, String> {
             end: data,
         };
 
-        let api_cmd = CString::new(cmd).unwrap();
-        let api_arg = CString::new(arg).unwrap();
 
         let status = switch_api_execute(
             api_cmd.as_ptr(),
@@ -94,7 +99,7 @@ pub fn json_api_exec(cmd: &str) -> Result<String, String> {
         if !cmd.starts_with("{") || !cmd.ends_with("}") {
             return Err(parse_error);
         }
-        let jcmd = CString::new(cmd).unwrap();
+        let jcmd = CString::new(cmd).map_err(|_| parse_error.clone())?;
         let jcmd = cJSON_Parse(jcmd.as_ptr());
         if jcmd.is_null() {
             return Err(parse_error);
@@ -127,11 +132,16 @@ pub fn sendevent<'a>(
     header: HashMap<String, String>,
     body: &'a str,
 ) -> Result<String, String> {
+    let subclass = to_cstring(subclass_name, "subclass")?;
+    let header = header
+        .iter()
+        .map(|(key, value)| Ok((to_cstring(key, "header name")?, to_cstring(value, "header value")?)))
+        .collect::<Result<Vec<_>, String>>()?;
+    let c_body = to_cstring(body, "body")?;
     let mut event = std::ptr::null_mut() as *mut switch_event_t;
     unsafe {
         let mut sub_name = std::ptr::null() as *const c_char;
         if id == switch_event_types_t::SWITCH_EVENT_CUSTOM.0 && !subclass_name.is_empty() {
-            let subclass = CString::new(subclass_name).unwrap();
             sub_name = subclass.as_ptr()
         }
         let status = switch_event_create_subclass_detailed(
@@ -157,9 +167,7 @@ pub fn sendevent<'a>(
             uuid_str.as_ptr(),
         );
 
-        for (key, value) in header {
-            let name = CString::new(key).unwrap();
-            let data = CString::new(value).unwrap();
+        for (name, data) in &header {
             switch_event_add_header_string(
                 event,
                 switch_stack_t::SWITCH_STACK_BOTTOM,
@@ -168,7 +176,6 @@ pub fn sendevent<'a>(
             );
         }
 
-        let c_body = CString::new(body).unwrap();
         switch_event_set_body(event, c_body.as_ptr());
 
         switch_event_fire_detailed(
@@ -188,6 +195,11 @@ pub fn sendmsg<'a>(uuid: &'a str, header: HashMap<String, String>) -> Result<Str
     if uuid.is_empty() {
         return Err(String::from("-ERR invalid session id"));
     }
+    let uuid_str = to_cstring(uuid, "session id")?;
+    let header = header
+        .iter()
+        .map(|(key, value)| Ok((to_cstring(key, "header name")?, to_cstring(value, "header value")?)))
+        .collect::<Result<Vec<_>, String>>()?;
 
     let mut event = std::ptr::null_mut() as *mut switch_event_t;
     unsafe {
@@ -204,9 +216,7 @@ pub fn sendmsg<'a>(uuid: &'a str, header: HashMap<String, String>) -> Result<Str
             return Err(String::from("create event error"));
         }
 
-        for (key, value) in header {
-            let name = CString::new(key).unwrap();
-            let data = CString::new(value).unwrap();
+        for (name, data) in &header {
             switch_event_add_header_string(
                 event,
                 switch_stack_t::SWITCH_STACK_BOTTOM,
@@ -215,7 +225,6 @@ pub fn sendmsg<'a>(uuid: &'a str, header: HashMap<String, String>) -> Result<Str
             );
         }
 
-        let uuid_str = CString::new(uuid).unwrap();
         let session = switch_core_session_perform_locate(
             uuid_str.as_ptr(),
             concat!(file!(), '\0').as_ptr() as *const std::os::raw::c_char,
@@ -242,6 +251,51 @@ pub fn sendmsg<'a>(uuid: &'a str, header: HashMap<String, String>) -> Result<Str
     Ok(String::from("+OK"))
 }
 
+/// uuid_exists
+/// check whether the session `uuid` exists
+pub fn uuid_exists(uuid: &str) -> bool {
+    if uuid.is_empty() {
+        return false;
+    }
+    let Ok(uuid_str) = CString::new(uuid) else {
+        return false;
+    };
+    unsafe { switch_ivr_uuid_exists(uuid_str.as_ptr()) == switch_bool_t::SWITCH_TRUE }
+}
+
+/// get_channel_variable
+/// get a channel variable of the session `uuid`
+pub fn get_channel_variable(uuid: &str, name: &str) -> Result<String, String> {
+    if uuid.is_empty() {
+        return Err(String::from("-ERR invalid session id"));
+    }
+    let uuid_str = to_cstring(uuid, "session id")?;
+    let var_name = to_cstring(name, "variable name")?;
+
+    unsafe {
+        let session = switch_core_session_perform_locate(
+            uuid_str.as_ptr(),
+            concat!(file!(), '\0').as_ptr() as *const std::os::raw::c_char,
+            std::ptr::null(),
+            line!() as std::os::raw::c_int,
+        );
+        if session.is_null() {
+            return Err(format!("-ERR invalid session id [{}]", uuid));
+        }
+
+        let channel = switch_core_session_get_channel(session);
+        let val = switch_channel_get_variable_dup(
+            channel,
+            var_name.as_ptr(),
+            switch_bool_t::SWITCH_FALSE,
+            -1,
+        );
+        let ret = switch_to_string(val);
+        switch_core_session_rwunlock(session);
+        Ok(ret)
+    }
+}
+
 /// xml_bind_search
 /// Add FreeSWITCH XMLBinding
 ///
@@ -321,6 +375,7 @@ pub fn xml_unbind_search(binding: u64) {
     }
 }
 
+#[derive(Debug, Clone, serde::Serialize)]
 pub struct SytemStatus {
     pub uptime: i64,
     pub version: String,
@@ -24,9 +24,12 @@ impl Stream {
         self.0
     }
     pub fn write(&self, s: &str) {
-        let ok = CString::new(s).expect("CString::new failed");
+        // Text after a NUL byte would be lost anyway.
+        let s = s.split('\0').next().unwrap_or_default();
+        let ok = CString::new(s).unwrap_or_default();
         unsafe {
-            (*self.0).write_function.unwrap()(self.0, ok.as_ptr());
+            // Never let the text be taken as a format string.
+            (*self.0).write_function.unwrap()(self.0, "%s\0".as_ptr() as *const c_char, ok.as_ptr());
         }
     }
 }